@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+
+use rocket::{Request, Response, Route};
+use rocket::response::{self, Responder};
+use rocket_contrib::json::Json;
+use serde::Serialize;
+
+use crate::config;
+use crate::ast::Document;
+use crate::pages;
+use crate::render;
+use crate::search::{self, SearchResult};
+use crate::site_cache;
+use crate::tokens::SiteContent;
+
+// a read only json api, mounted at /api. every response can be fetched from other origins
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content_compiler;
+
+    fn post(slug: &str, tags: &str, timestamp: u128, updated: Option<u128>) -> SiteContent {
+        let contents = format!("#[title:Post {}|tags:{}]\n\nHello", slug, tags);
+        let document = content_compiler::compile_hmm(&contents, &format!("/home/me/content/{}.hmm", slug), &mut Vec::new());
+        SiteContent {
+            file_name: format!("/home/me/content/{}.hmm", slug),
+            page_hash: format!("hash-{}", slug),
+            slug: slug.to_string(),
+            title: format!("/home/me/content/{}.hmm", slug),
+            template: None,
+            timestamp,
+            created: None,
+            updated,
+            revisions: Vec::new(),
+            page_tokens: document.to_page_tokens(),
+            document,
+        }
+    }
+
+    fn slugs(list: &PostList) -> Vec<&str> {
+        return list.posts.iter().map(|p| p.slug.as_str()).collect();
+    }
+
+    #[test]
+    fn posts_are_paged_newest_first() {
+        let posts: Vec<SiteContent> = (1..=5).map(|i| post(&format!("p{}", i), "", i, None)).collect();
+
+        let list = list_posts(posts.clone(), Some(1), Some(2), None, None);
+        assert_eq!(slugs(&list), vec!["p5", "p4"]);
+        assert_eq!((list.page, list.per_page, list.total), (1, 2, 5));
+        assert_eq!(slugs(&list_posts(posts.clone(), Some(3), Some(2), None, None)), vec!["p1"]);
+        assert!(list_posts(posts.clone(), Some(4), Some(2), None, None).posts.is_empty());
+
+        // out of range values are clamped rather than refused
+        let list = list_posts(posts, Some(0), Some(1000), None, None);
+        assert_eq!((list.page, list.per_page), (1, MAX_PER_PAGE));
+    }
+
+    #[test]
+    fn posts_filter_by_tag_and_since() {
+        let posts = vec![
+            post("old", "rust", 100, None),
+            post("edited", "thinking", 100, Some(300)),
+            post("new", "rust,thinking", 200, None),
+        ];
+
+        assert_eq!(slugs(&list_posts(posts.clone(), None, None, Some("rust".to_string()), None)), vec!["new", "old"]);
+        assert_eq!(slugs(&list_posts(posts.clone(), None, None, None, Some(200))), vec!["new", "edited"]);
+        assert_eq!(slugs(&list_posts(posts, None, None, Some("thinking".to_string()), Some(250))), vec!["edited"]);
+    }
+
+    #[test]
+    fn posts_leave_out_local_paths() {
+        let json = serde_json::to_string(&ApiPost::from(post("hello", "rust", 100, None))).unwrap();
+
+        assert!(json.contains("\"title\":\"Post hello\""));
+        assert!(json.contains("\"url\":\"/post/hash-hello\""));
+        assert!(json.contains("\"tags\":[\"rust\"]"));
+        assert!(!json.contains("/home/me"));
+    }
+}
+
+const DEFAULT_PER_PAGE: usize = 10;
+const MAX_PER_PAGE: usize = 50;
+
+pub struct ApiResponse<T>(pub T);
+
+impl<'r, T: Serialize> Responder<'r> for ApiResponse<T> {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        return Response::build_from(Json(self.0).respond_to(request)?)
+            .raw_header("Access-Control-Allow-Origin", "*")
+            .ok();
+    }
+}
+
+// what readers of a post need, along with its rendered body. nothing about where it lives on disk
+#[derive(Serialize, Debug)]
+pub struct ApiPost {
+    pub slug: String,
+    pub url: String,
+    pub title: String,
+    pub timestamp: u128,
+    pub created: Option<u128>,
+    pub updated: Option<u128>,
+    pub tags: Vec<String>,
+    pub html: String,
+    pub document: Document,
+}
+
+impl From<SiteContent> for ApiPost {
+    fn from(post: SiteContent) -> ApiPost {
+        ApiPost {
+            url: format!("/post/{}", post.page_hash),
+            title: post.display_title(),
+            tags: post.tags(),
+            html: render::render_document(&post.document),
+            slug: post.slug,
+            timestamp: post.timestamp,
+            created: post.created,
+            updated: post.updated,
+            document: post.document,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct PostList {
+    pub posts: Vec<ApiPost>,
+    pub page: usize,
+    pub per_page: usize,
+    pub total: usize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: usize,
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![posts, post, tags, search]
+}
+
+// newest first. `since` is a unix timestamp and keeps posts published or updated after it
+#[get("/posts?<page>&<per_page>&<tag>&<since>")]
+fn posts(page: Option<usize>, per_page: Option<usize>, tag: Option<String>, since: Option<u128>) -> ApiResponse<PostList> {
+    ApiResponse(list_posts(site_cache::get_all_site_content(), page, per_page, tag, since))
+}
+
+fn list_posts(posts: Vec<SiteContent>, page: Option<usize>, per_page: Option<usize>, tag: Option<String>, since: Option<u128>) -> PostList {
+    let mut posts: Vec<SiteContent> = posts.into_iter()
+        .filter(|p| tag.as_ref().map(|t| p.tags().contains(t)).unwrap_or(true))
+        .filter(|p| since.map(|s| p.updated.unwrap_or(p.timestamp).max(p.timestamp) >= s).unwrap_or(true))
+        .collect();
+    posts.sort();
+
+    let page = page.unwrap_or(1).max(1);
+    let per_page = per_page.unwrap_or(DEFAULT_PER_PAGE).max(1).min(MAX_PER_PAGE);
+    let total = posts.len();
+
+    PostList {
+        posts: posts.into_iter().skip((page - 1) * per_page).take(per_page).map(ApiPost::from).collect(),
+        page,
+        per_page,
+        total,
+    }
+}
+
+#[get("/posts/<slug>")]
+fn post(slug: String) -> Option<ApiResponse<ApiPost>> {
+    let post = site_cache::get_all_site_content().into_iter().find(|p| p.slug == slug)?;
+    Some(ApiResponse(ApiPost::from(post)))
+}
+
+// every tag in use with how many posts have it, alphabetical
+#[get("/tags")]
+fn tags() -> ApiResponse<Vec<TagCount>> {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for post in site_cache::get_all_site_content() {
+        for tag in post.tags() {
+            *counts.entry(tag).or_insert(0) += 1;
+        }
+    }
+
+    ApiResponse(counts.into_iter().map(|(tag, count)| TagCount { tag, count }).collect())
+}
+
+#[get("/search?<q>")]
+fn search(q: String) -> Option<ApiResponse<Vec<SearchResult>>> {
+    if !config::get_config().site.features.search {
+        return None;
+    }
+    return Some(ApiResponse(search::search(&q, pages::SEARCH_LIMIT)));
+}
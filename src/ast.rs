@@ -0,0 +1,990 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::tag_handlers;
+use crate::tokens::PageToken;
+
+// a post as a tree: a document is a list of blocks and paragraphs hold inline content, which can
+// nest (a link inside emphasis inside strong...). the compiler builds it straight from its passes.
+// `to_page_tokens` flattens it for templates and tools which still want the old flat list, and
+// `from_page_tokens` reads the flat lists the markdown import and tag handlers still make
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(token_type: &str, meta: Vec<(&str, &str)>) -> PageToken {
+        PageToken {
+            token_type: token_type.to_string(),
+            meta: meta.into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        }
+    }
+
+    #[test]
+    fn flat_tokens_become_a_tree() {
+        let document = Document::from_page_tokens(&vec![
+            token("title", vec![("title", "Hi"), ("timestamp", "1605073296"), ("tags", "a, b"), ("mood", "calm")]),
+            token("para_start", vec![]),
+            token("strong_start", vec![]),
+            token("span", vec![("text", "bold ")]),
+            token("em_start", vec![]),
+            token("url", vec![("url", "test.com"), ("text", "both")]),
+            token("strong_end", vec![]),
+            token("span", vec![("text", " italic")]),
+            token("para_end", vec![]),
+        ]);
+
+        assert_eq!(document.blocks, vec![
+            Block::Title {
+                title: "Hi".into(),
+                timestamp: Some(1605073296),
+                tags: vec!["a".into(), "b".into()],
+                slug: None,
+                template: None,
+                extra: vec![("mood".to_string(), "calm".to_string())].into_iter().collect(),
+            },
+            Block::Paragraph {
+                children: vec![
+                    Inline::Strong {
+                        children: vec![
+                            Inline::Text { text: "bold ".into() },
+                            Inline::Emphasis {
+                                children: vec![Inline::Link { url: "test.com".into(), href: "https://test.com".into(), text: "both".into() }],
+                            },
+                        ],
+                    },
+                    // the emphasis that was still open when strong closed carries on
+                    Inline::Emphasis { children: vec![Inline::Text { text: " italic".into() }] },
+                ],
+            },
+        ]);
+    }
+
+    #[test]
+    fn legacy_tokens_round_trip() {
+        let tokens = vec![
+            token("header", vec![("header", "<empty>"), ("text", "Title"), ("link", "post/1")]),
+            token("para_start", vec![]),
+            token("span", vec![("text", "Some ")]),
+            token("em_start", vec![]),
+            token("span", vec![("text", "text")]),
+            token("em_end", vec![]),
+            token("para_end", vec![]),
+            token("github_gist", vec![("github_gist", "<empty>"), ("url", "x.js")]),
+            token("footer", vec![]),
+        ];
+
+        let document = Document::from_page_tokens(&tokens);
+        assert_eq!(document.to_page_tokens(), tokens);
+        assert_eq!(Document::from_page_tokens(&document.to_page_tokens()), document);
+    }
+
+    #[test]
+    fn lists_nest() {
+        let tokens = vec![
+            token("list_start", vec![]),
+            token("item_start", vec![]),
+            token("span", vec![("text", "one")]),
+            token("list_start", vec![("ordered", "<empty>")]),
+            token("item_start", vec![]),
+            token("span", vec![("text", "inner")]),
+            token("item_end", vec![]),
+            token("list_end", vec![]),
+            token("item_end", vec![]),
+            token("item_start", vec![]),
+            token("span", vec![("text", "two")]),
+            token("item_end", vec![]),
+            token("list_end", vec![]),
+        ];
+
+        let document = Document::from_page_tokens(&tokens);
+        let text = |t: &str| vec![Inline::Text { text: t.into() }];
+        assert_eq!(document.blocks, vec![Block::List {
+            ordered: false,
+            items: vec![
+                ListItem {
+                    children: text("one"),
+                    sublists: vec![Block::List { ordered: true, items: vec![ListItem { children: text("inner"), sublists: vec![] }] }],
+                },
+                ListItem { children: text("two"), sublists: vec![] },
+            ],
+        }]);
+        assert_eq!(document.to_page_tokens(), tokens);
+    }
+
+    #[test]
+    fn quotes_hold_blocks() {
+        let tokens = vec![
+            token("quote_start", vec![("quote", "<empty>"), ("cite", "Someone")]),
+            token("para_start", vec![]),
+            token("span", vec![("text", "one")]),
+            token("para_end", vec![]),
+            token("para_start", vec![]),
+            token("span", vec![("text", "two")]),
+            token("para_end", vec![]),
+            token("quote_end", vec![]),
+        ];
+
+        let document = Document::from_page_tokens(&tokens);
+        let paragraph = |t: &str| Block::Paragraph { children: vec![Inline::Text { text: t.into() }] };
+        assert_eq!(document.blocks, vec![Block::Quote {
+            cite: Some("Someone".into()),
+            source: None,
+            blocks: vec![paragraph("one"), paragraph("two")],
+        }]);
+        assert_eq!(document.to_page_tokens(), tokens);
+    }
+
+    #[test]
+    fn tables_split_head_and_body() {
+        let tokens = vec![
+            token("table_start", vec![("align", "right,")]),
+            token("row_start", vec![("header", "<empty>")]),
+            token("cell_start", vec![]),
+            token("span", vec![("text", "a")]),
+            token("cell_end", vec![]),
+            token("row_end", vec![]),
+            token("row_start", vec![]),
+            token("cell_start", vec![]),
+            token("cell_end", vec![]),
+            token("cell_start", vec![]),
+            token("span", vec![("text", "b")]),
+            token("cell_end", vec![]),
+            token("row_end", vec![]),
+            token("table_end", vec![]),
+        ];
+
+        let document = Document::from_page_tokens(&tokens);
+        assert_eq!(document.blocks, vec![Block::Table {
+            caption: None,
+            align: vec![Align::Right, Align::Auto],
+            head: vec![vec![vec![Inline::Text { text: "a".into() }]]],
+            body: vec![vec![vec![], vec![Inline::Text { text: "b".into() }]]],
+        }]);
+        assert_eq!(document.to_page_tokens(), tokens);
+    }
+
+    #[test]
+    fn images_in_the_same_gallery_share_a_grid() {
+        let image = |src: &str| Inline::Image {
+            src: src.into(),
+            alt: None,
+            caption: None,
+            lightbox: false,
+            gallery: Some("h-boards".into()),
+            width: None,
+            height: None,
+        };
+        let tokens = vec![
+            token("para_start", vec![]),
+            token("image", vec![("image", "a.png"), ("gallery", "h-boards")]),
+            token("para_end", vec![]),
+            token("para_start", vec![]),
+            token("image", vec![("image", "b.png"), ("gallery", "h-boards")]),
+            token("para_end", vec![]),
+            token("para_start", vec![]),
+            token("span", vec![("text", "one")]),
+            token("para_end", vec![]),
+        ];
+
+        let document = Document::from_page_tokens(&tokens);
+        assert_eq!(document.blocks, vec![
+            Block::Gallery { name: "h-boards".into(), images: vec![image("a.png"), image("b.png")] },
+            Block::Paragraph { children: vec![Inline::Text { text: "one".into() }] },
+        ]);
+        // and it comes back as a gallery container
+        assert_eq!(Document::from_page_tokens(&document.to_page_tokens()), document);
+    }
+
+    #[test]
+    fn nodes_are_tagged_with_their_type() {
+        let document = Document { blocks: vec![Block::Paragraph { children: vec![Inline::LineBreak] }] };
+        let json = serde_json::to_string(&document).unwrap();
+
+        assert_eq!(json, r#"{"blocks":[{"type":"paragraph","children":[{"type":"line_break"}]}]}"#);
+        assert_eq!(serde_json::from_str::<Document>(&json).unwrap(), document);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Document {
+    pub blocks: Vec<Block>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Block {
+    // `extra` has any other keys on the title tag, so nothing written on it gets lost
+    Title {
+        title: String,
+        timestamp: Option<u64>,
+        tags: Vec<String>,
+        slug: Option<String>,
+        template: Option<String>,
+        extra: HashMap<String, String>,
+    },
+    Header { text: String, date: Option<String>, link: Option<String> },
+    Updated { date: String, diff: String },
+    Paragraph { children: Vec<Inline> },
+    List { ordered: bool, items: Vec<ListItem> },
+    // `#[quote|cite:...|source:...]` ... `#[/quote]`
+    Quote { cite: Option<String>, source: Option<String>, blocks: Vec<Block> },
+    CodeBlock { text: String },
+    Rule,
+    // between posts on the index
+    Footer,
+    Table { caption: Option<String>, align: Vec<Align>, head: Vec<TableRow>, body: Vec<TableRow> },
+    // `mathml` is missing when the TeX couldn't be converted
+    Math { tex: String, mathml: Option<String> },
+    // a grid of images which the lightbox steps through together, `images` only holds images
+    Gallery { name: String, images: Vec<Inline> },
+    // an image with a caption on its own, `image` is always an image
+    Figure { image: Inline },
+    Video { src: String, poster: Option<String>, lightbox: bool },
+    Audio { src: String },
+    // every footnote in the post, added at the end by the compiler
+    Footnotes { notes: Vec<Footnote> },
+    // block tags from handlers the ast doesn't know about
+    Tag { tag_type: String, meta: HashMap<String, String> },
+    // same again for container tags, with whatever was between the start and end tag
+    Container { tag_type: String, meta: HashMap<String, String>, blocks: Vec<Block> },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ListItem {
+    pub children: Vec<Inline>,
+    // only ever Block::List
+    pub sublists: Vec<Block>,
+}
+
+// a row of cells, each holding inline content
+pub type TableRow = Vec<Vec<Inline>>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Align {
+    // whatever the browser does
+    Auto,
+    Left,
+    Center,
+    Right,
+}
+
+impl Align {
+    pub fn parse(align: &str) -> Align {
+        return match align {
+            "left" => Align::Left,
+            "center" => Align::Center,
+            "right" => Align::Right,
+            _ => Align::Auto,
+        };
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        return match self {
+            Align::Auto => "",
+            Align::Left => "left",
+            Align::Center => "center",
+            Align::Right => "right",
+        };
+    }
+}
+
+// `anchor` is unique across the whole site, the reference and the note link to each other with it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Footnote {
+    pub number: usize,
+    pub anchor: String,
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Inline {
+    Text { text: String },
+    Emphasis { children: Vec<Inline> },
+    Strong { children: Vec<Inline> },
+    Code { text: String },
+    LineBreak,
+    // `url` is what the post says, `href` where it actually points
+    Link { url: String, href: String, text: String },
+    // `gallery` is the name of the gallery it's in, unique across the site. the size is read from
+    // the file by the compiler, when it can be
+    Image {
+        src: String,
+        alt: Option<String>,
+        caption: Option<String>,
+        lightbox: bool,
+        gallery: Option<String>,
+        width: Option<u32>,
+        height: Option<u32>,
+    },
+    // a `#[fn:...]` once it's been numbered
+    FootnoteRef { number: usize, anchor: String, text: String },
+    Math { tex: String, mathml: Option<String> },
+    // inline tags from handlers the ast doesn't know about
+    Tag { tag_type: String, meta: HashMap<String, String> },
+}
+
+const FLAG: &str = "<empty>";
+
+// tokens that can't live inside a paragraph
+pub fn is_block_token(token_type: &str) -> bool {
+    return tag_handlers::is_block(token_type)
+        || ["updated", "code_block", "rule", "footer"].contains(&token_type);
+}
+
+// containers come through as `<name>_start` ... `<name>_end`
+fn container_start(token_type: &str) -> Option<&str> {
+    return token_type.strip_suffix("_start").filter(|name| tag_handlers::is_container(name));
+}
+
+fn container_end(token_type: &str) -> Option<&str> {
+    return token_type.strip_suffix("_end").filter(|name| tag_handlers::is_container(name));
+}
+
+fn meta(token: &PageToken, key: &str) -> String {
+    return token.meta.get(key).cloned().unwrap_or_default();
+}
+
+fn optional_meta(token: &PageToken, key: &str) -> Option<String> {
+    return token.meta.get(key).filter(|v| *v != FLAG).cloned();
+}
+
+fn page_token(token_type: &str, meta: Vec<(&str, Option<String>)>) -> PageToken {
+    return PageToken {
+        token_type: token_type.to_string(),
+        meta: meta.into_iter().filter_map(|(k, v)| v.map(|v| (k.to_string(), v))).collect(),
+    };
+}
+
+const TITLE_KEYS: [&str; 5] = ["title", "timestamp", "tags", "slug", "template"];
+
+// a block tag's token as a node. the compiler and the flat token parser both use these, so a tag
+// comes out the same whichever way it got here
+pub fn block_from(token: &PageToken) -> Block {
+    return match token.token_type.as_str() {
+        // a timestamp which isn't a number is left out, the title tag's schema reports it
+        "title" => Block::Title {
+            title: meta(token, "title").trim().to_string(),
+            timestamp: token.meta.get("timestamp").and_then(|t| t.parse().ok()),
+            tags: optional_meta(token, "tags")
+                .map(|tags| tags.split(',').map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect())
+                .unwrap_or_default(),
+            slug: optional_meta(token, "slug"),
+            template: optional_meta(token, "template"),
+            extra: token.meta.iter()
+                .filter(|(k, _)| !TITLE_KEYS.contains(&k.as_str()))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+        },
+        "header" => Block::Header {
+            text: meta(token, "text"),
+            date: optional_meta(token, "date"),
+            link: optional_meta(token, "link"),
+        },
+        "updated" => Block::Updated { date: meta(token, "date"), diff: meta(token, "diff") },
+        "code_block" => Block::CodeBlock { text: meta(token, "text") },
+        "rule" => Block::Rule,
+        "footer" => Block::Footer,
+        "video" => Block::Video {
+            src: meta(token, "video"),
+            poster: optional_meta(token, "poster"),
+            lightbox: token.meta.contains_key("lightbox"),
+        },
+        "audio" => Block::Audio { src: meta(token, "audio") },
+        _ => Block::Tag { tag_type: token.token_type.clone(), meta: token.meta.clone() },
+    };
+}
+
+pub fn container_from(name: &str, start: &PageToken, blocks: Vec<Block>) -> Block {
+    return match name {
+        "quote" => Block::Quote { cite: optional_meta(start, "cite"), source: optional_meta(start, "source"), blocks },
+        "gallery" => Block::Gallery { name: meta(start, "name"), images: gallery_images(blocks) },
+        _ => Block::Container { tag_type: name.to_string(), meta: start.meta.clone(), blocks },
+    };
+}
+
+// the images out of whatever was written in a gallery, anything else is left out
+fn gallery_images(blocks: Vec<Block>) -> Vec<Inline> {
+    return blocks.into_iter()
+        .flat_map(|b| match b {
+            Block::Paragraph { children } => children,
+            // images that already made a grid of their own
+            Block::Gallery { images, .. } => images,
+            _ => Vec::new(),
+        })
+        .filter(|i| matches!(i, Inline::Image { .. }))
+        .collect();
+}
+
+// the gallery a paragraph of nothing but images is in, if they're all in the same one
+fn paragraph_gallery(block: &Block) -> Option<String> {
+    let children = match block {
+        Block::Paragraph { children } => children,
+        _ => return None,
+    };
+
+    let mut name: Option<&String> = None;
+    for child in children {
+        match child {
+            Inline::Image { gallery: Some(gallery), .. } if name.map(|n| n == gallery).unwrap_or(true) => name = Some(gallery),
+            Inline::Text { text } if text.trim().is_empty() => {}
+            Inline::LineBreak => {}
+            _ => return None,
+        }
+    }
+    return name.cloned();
+}
+
+// images with a `gallery:` key get a grid too, when they're on their own one after the other
+fn group_galleries(blocks: Vec<Block>) -> Vec<Block> {
+    let mut grouped: Vec<Block> = Vec::new();
+    for block in blocks {
+        let name = match paragraph_gallery(&block) {
+            Some(name) => name,
+            None => {
+                grouped.push(block);
+                continue;
+            }
+        };
+
+        let images = gallery_images(vec![block]);
+        match grouped.last_mut() {
+            Some(Block::Gallery { name: last, images: last_images }) if *last == name => last_images.extend(images),
+            _ => grouped.push(Block::Gallery { name, images }),
+        }
+    }
+    return grouped;
+}
+
+// what every list of blocks goes through once it's built
+pub fn tidy_blocks(blocks: Vec<Block>) -> Vec<Block> {
+    return make_figures(group_galleries(blocks));
+}
+
+// a figure can't go in a paragraph, so a captioned image that's on its own is taken out of it
+fn make_figures(blocks: Vec<Block>) -> Vec<Block> {
+    return blocks.into_iter().map(|block| {
+        let image = match &block {
+            Block::Paragraph { children } => {
+                let mut content = children.iter().filter(|c| match c {
+                    Inline::Text { text } => !text.trim().is_empty(),
+                    Inline::LineBreak => false,
+                    _ => true,
+                });
+                match (content.next(), content.next()) {
+                    (Some(image @ Inline::Image { caption: Some(_), .. }), None) => Some(image.clone()),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        return match image {
+            Some(image) => Block::Figure { image },
+            None => block,
+        };
+    }).collect();
+}
+
+pub fn inline_from(token: &PageToken) -> Inline {
+    return match token.token_type.as_str() {
+        "span" => Inline::Text { text: meta(token, "text") },
+        "code" => Inline::Code { text: meta(token, "text") },
+        "line_break" => Inline::LineBreak,
+        "url" => {
+            let url = meta(token, "url");
+            let href = token.meta.get("href").cloned().unwrap_or(tag_handlers::link_href(&url));
+            Inline::Link { url, href, text: meta(token, "text") }
+        }
+        "image" => Inline::Image {
+            src: meta(token, "image"),
+            alt: optional_meta(token, "alt"),
+            caption: optional_meta(token, "caption"),
+            lightbox: token.meta.contains_key("lightbox"),
+            gallery: optional_meta(token, "gallery"),
+            width: token.meta.get("width").and_then(|w| w.parse().ok()),
+            height: token.meta.get("height").and_then(|h| h.parse().ok()),
+        },
+        "math" => Inline::Math { tex: meta(token, "math"), mathml: optional_meta(token, "mathml") },
+        "fn" if token.meta.contains_key("number") => Inline::FootnoteRef {
+            number: meta(token, "number").parse().unwrap_or(0),
+            anchor: meta(token, "anchor"),
+            text: meta(token, "fn"),
+        },
+        _ => Inline::Tag { tag_type: token.token_type.clone(), meta: token.meta.clone() },
+    };
+}
+
+fn footnote_from(token: &PageToken) -> Footnote {
+    return Footnote {
+        number: meta(token, "number").parse().unwrap_or(0),
+        anchor: meta(token, "anchor"),
+        text: meta(token, "text"),
+    };
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Marker {
+    Emphasis,
+    Strong,
+}
+
+fn marker(token_type: &str) -> Option<(Marker, bool)> {
+    return match token_type {
+        "em_start" => Some((Marker::Emphasis, true)),
+        "em_end" => Some((Marker::Emphasis, false)),
+        "strong_start" => Some((Marker::Strong, true)),
+        "strong_end" => Some((Marker::Strong, false)),
+        _ => None,
+    };
+}
+
+// builds a paragraph's inline tree out of start/end markers, which don't always line up
+struct InlineBuilder {
+    // the paragraph itself is the bottom frame
+    frames: Vec<(Option<Marker>, Vec<Inline>)>,
+}
+
+impl InlineBuilder {
+    fn new() -> InlineBuilder {
+        InlineBuilder { frames: vec![(None, Vec::new())] }
+    }
+
+    fn close_frame(&mut self) {
+        let (marker, children) = self.frames.pop().unwrap();
+        if children.is_empty() { return; }
+
+        let node = match marker {
+            Some(Marker::Emphasis) => Inline::Emphasis { children },
+            Some(Marker::Strong) => Inline::Strong { children },
+            None => unreachable!(),
+        };
+        self.frames.last_mut().unwrap().1.push(node);
+    }
+
+    fn push(&mut self, token: &PageToken) {
+        match marker(&token.token_type) {
+            Some((kind, true)) => self.frames.push((Some(kind), Vec::new())),
+            Some((kind, false)) => {
+                let position = match self.frames.iter().rposition(|(m, _)| *m == Some(kind)) {
+                    Some(p) => p,
+                    // closing something that was never opened
+                    None => return,
+                };
+                // close anything opened after it, then open those again
+                let reopen: Vec<Option<Marker>> = self.frames[position + 1..].iter().map(|(m, _)| *m).collect();
+                while self.frames.len() > position {
+                    self.close_frame();
+                }
+                self.frames.extend(reopen.into_iter().map(|m| (m, Vec::new())));
+            }
+            None => self.frames.last_mut().unwrap().1.push(inline_from(token)),
+        }
+    }
+
+    fn finish_inlines(mut self) -> Vec<Inline> {
+        while self.frames.len() > 1 {
+            self.close_frame();
+        }
+        return self.frames.pop().unwrap().1;
+    }
+
+    fn finish(self) -> Block {
+        return Block::Paragraph { children: self.finish_inlines() };
+    }
+}
+
+type TokenIter<'a> = std::iter::Peekable<std::slice::Iter<'a, PageToken>>;
+
+// everything up to the matching list_end, an unterminated list ends with the tokens
+fn parse_list(start: &PageToken, iter: &mut TokenIter) -> Block {
+    let mut items: Vec<ListItem> = Vec::new();
+    while let Some(token) = iter.next() {
+        match token.token_type.as_str() {
+            "list_end" => break,
+            "item_start" => items.push(parse_list_item(iter)),
+            // anything loose between items is dropped
+            _ => {}
+        }
+    }
+    return Block::List { ordered: start.meta.contains_key("ordered"), items };
+}
+
+fn parse_list_item(iter: &mut TokenIter) -> ListItem {
+    let mut inlines = InlineBuilder::new();
+    let mut sublists: Vec<Block> = Vec::new();
+
+    while let Some(token) = iter.peek() {
+        match token.token_type.as_str() {
+            "item_end" => {
+                iter.next();
+                break;
+            }
+            // the next item or the end of the list means this one wasn't closed
+            "item_start" | "list_end" => break,
+            "list_start" => {
+                let start = iter.next().unwrap();
+                sublists.push(parse_list(start, iter));
+            }
+            // items hold inline content directly, markdown's loose lists wrap it in paragraphs
+            "para_start" | "para_end" => { iter.next(); }
+            _ => inlines.push(iter.next().unwrap()),
+        }
+    }
+
+    return ListItem { children: inlines.finish_inlines(), sublists };
+}
+
+// rows up to the matching table_end, the same way lists are parsed
+fn parse_table(start: &PageToken, iter: &mut TokenIter) -> Block {
+    let mut head: Vec<TableRow> = Vec::new();
+    let mut body: Vec<TableRow> = Vec::new();
+
+    while let Some(token) = iter.next() {
+        match token.token_type.as_str() {
+            "table_end" => break,
+            "row_start" => {
+                let row = parse_table_row(iter);
+                if token.meta.contains_key("header") { head.push(row); } else { body.push(row); }
+            }
+            _ => {}
+        }
+    }
+
+    let align = optional_meta(start, "align")
+        .filter(|a| !a.is_empty())
+        .map(|a| a.split(',').map(Align::parse).collect())
+        .unwrap_or_default();
+    return Block::Table { caption: optional_meta(start, "caption"), align, head, body };
+}
+
+fn parse_table_row(iter: &mut TokenIter) -> TableRow {
+    let mut cells: TableRow = Vec::new();
+    let mut cell: Option<InlineBuilder> = None;
+
+    while let Some(token) = iter.peek() {
+        match token.token_type.as_str() {
+            // the end of the table means this row wasn't closed
+            "table_end" | "row_start" => break,
+            "row_end" => {
+                iter.next();
+                break;
+            }
+            "cell_start" | "cell_end" => {
+                if let Some(builder) = cell.take() {
+                    cells.push(builder.finish_inlines());
+                }
+                if iter.next().unwrap().token_type == "cell_start" {
+                    cell = Some(InlineBuilder::new());
+                }
+            }
+            // anything between cells is dropped
+            _ => {
+                let token = iter.next().unwrap();
+                if let Some(builder) = cell.as_mut() {
+                    builder.push(token);
+                }
+            }
+        }
+    }
+
+    if let Some(builder) = cell {
+        cells.push(builder.finish_inlines());
+    }
+    return cells;
+}
+
+fn parse_footnotes(iter: &mut TokenIter) -> Block {
+    let mut notes: Vec<Footnote> = Vec::new();
+    while let Some(token) = iter.next() {
+        match token.token_type.as_str() {
+            "footnotes_end" => break,
+            "footnote" => notes.push(footnote_from(token)),
+            _ => {}
+        }
+    }
+    return Block::Footnotes { notes };
+}
+
+fn inline_to_page_tokens(inline: &Inline, tokens: &mut Vec<PageToken>) {
+    match inline {
+        Inline::Text { text } => tokens.push(page_token("span", vec![("text", Some(text.clone()))])),
+        Inline::Emphasis { children } | Inline::Strong { children } => {
+            let name = if let Inline::Emphasis { .. } = inline { "em" } else { "strong" };
+            tokens.push(page_token(&format!("{}_start", name), vec![]));
+            children.iter().for_each(|c| inline_to_page_tokens(c, tokens));
+            tokens.push(page_token(&format!("{}_end", name), vec![]));
+        }
+        Inline::Code { text } => tokens.push(page_token("code", vec![("text", Some(text.clone()))])),
+        Inline::LineBreak => tokens.push(page_token("line_break", vec![])),
+        Inline::Link { url, href, text } => tokens.push(page_token("url", vec![
+            ("url", Some(url.clone())),
+            ("href", Some(href.clone())),
+            ("text", Some(text.clone())),
+        ])),
+        Inline::Image { src, alt, caption, lightbox, gallery, width, height } => tokens.push(page_token("image", vec![
+            ("image", Some(src.clone())),
+            ("alt", alt.clone()),
+            ("caption", caption.clone()),
+            ("lightbox", if *lightbox { Some(FLAG.to_string()) } else { None }),
+            ("gallery", gallery.clone()),
+            ("width", width.map(|w| w.to_string())),
+            ("height", height.map(|h| h.to_string())),
+        ])),
+        Inline::FootnoteRef { number, anchor, text } => tokens.push(page_token("fn", vec![
+            ("fn", Some(text.clone())),
+            ("number", Some(number.to_string())),
+            ("anchor", Some(anchor.clone())),
+        ])),
+        Inline::Math { tex, mathml } => tokens.push(page_token("math", vec![
+            ("math", Some(tex.clone())),
+            ("mathml", mathml.clone()),
+        ])),
+        Inline::Tag { tag_type, meta } => tokens.push(PageToken { token_type: tag_type.clone(), meta: meta.clone() }),
+    }
+}
+
+fn block_to_page_tokens(block: &Block, tokens: &mut Vec<PageToken>) {
+    match block {
+        Block::Title { title, timestamp, tags, slug, template, extra } => {
+            let mut token = page_token("title", vec![
+                ("title", Some(title.clone())),
+                ("timestamp", timestamp.map(|t| t.to_string())),
+                ("tags", if tags.is_empty() { None } else { Some(tags.join(",")) }),
+                ("slug", slug.clone()),
+                ("template", template.clone()),
+            ]);
+            token.meta.extend(extra.iter().map(|(k, v)| (k.clone(), v.clone())));
+            tokens.push(token);
+        }
+        Block::Header { text, date, link } => tokens.push(page_token("header", vec![
+            ("header", Some(FLAG.to_string())),
+            ("text", Some(text.clone())),
+            ("date", date.clone()),
+            ("link", link.clone()),
+        ])),
+        Block::Updated { date, diff } => tokens.push(page_token("updated", vec![
+            ("date", Some(date.clone())),
+            ("diff", Some(diff.clone())),
+        ])),
+        Block::Paragraph { children } => {
+            tokens.push(page_token("para_start", vec![]));
+            children.iter().for_each(|c| inline_to_page_tokens(c, tokens));
+            tokens.push(page_token("para_end", vec![]));
+        }
+        Block::List { ordered, items } => {
+            let ordered = if *ordered { Some(FLAG.to_string()) } else { None };
+            tokens.push(page_token("list_start", vec![("ordered", ordered)]));
+            for item in items {
+                tokens.push(page_token("item_start", vec![]));
+                item.children.iter().for_each(|c| inline_to_page_tokens(c, tokens));
+                item.sublists.iter().for_each(|b| block_to_page_tokens(b, tokens));
+                tokens.push(page_token("item_end", vec![]));
+            }
+            tokens.push(page_token("list_end", vec![]));
+        }
+        Block::Quote { cite, source, blocks } => {
+            tokens.push(page_token("quote_start", vec![
+                ("quote", Some(FLAG.to_string())),
+                ("cite", cite.clone()),
+                ("source", source.clone()),
+            ]));
+            blocks.iter().for_each(|b| block_to_page_tokens(b, tokens));
+            tokens.push(page_token("quote_end", vec![]));
+        }
+        Block::CodeBlock { text } => tokens.push(page_token("code_block", vec![("text", Some(text.clone()))])),
+        Block::Rule => tokens.push(page_token("rule", vec![])),
+        Block::Footer => tokens.push(page_token("footer", vec![])),
+        Block::Table { caption, align, head, body } => {
+            let align: Vec<&str> = align.iter().map(|a| a.as_str()).collect();
+            tokens.push(page_token("table_start", vec![("caption", caption.clone()), ("align", Some(align.join(",")))]));
+            let rows = head.iter().map(|r| (r, true)).chain(body.iter().map(|r| (r, false)));
+            for (row, header) in rows {
+                tokens.push(page_token("row_start", vec![("header", if header { Some(FLAG.to_string()) } else { None })]));
+                for cell in row {
+                    tokens.push(page_token("cell_start", vec![]));
+                    cell.iter().for_each(|c| inline_to_page_tokens(c, tokens));
+                    tokens.push(page_token("cell_end", vec![]));
+                }
+                tokens.push(page_token("row_end", vec![]));
+            }
+            tokens.push(page_token("table_end", vec![]));
+        }
+        Block::Math { tex, mathml } => tokens.push(page_token("math", vec![
+            ("math", Some(tex.clone())),
+            ("mathml", mathml.clone()),
+            ("display", Some(FLAG.to_string())),
+        ])),
+        Block::Gallery { name, images } => {
+            tokens.push(page_token("gallery_start", vec![("gallery", Some(FLAG.to_string())), ("name", Some(name.clone()))]));
+            images.iter().for_each(|i| inline_to_page_tokens(i, tokens));
+            tokens.push(page_token("gallery_end", vec![]));
+        }
+        Block::Figure { image } => {
+            tokens.push(page_token("para_start", vec![]));
+            inline_to_page_tokens(image, tokens);
+            tokens.push(page_token("para_end", vec![]));
+        }
+        Block::Video { src, poster, lightbox } => tokens.push(page_token("video", vec![
+            ("video", Some(src.clone())),
+            ("poster", poster.clone()),
+            ("lightbox", if *lightbox { Some(FLAG.to_string()) } else { None }),
+        ])),
+        Block::Audio { src } => tokens.push(page_token("audio", vec![("audio", Some(src.clone()))])),
+        Block::Footnotes { notes } => {
+            tokens.push(page_token("footnotes_start", vec![]));
+            for note in notes {
+                tokens.push(page_token("footnote", vec![
+                    ("text", Some(note.text.clone())),
+                    ("number", Some(note.number.to_string())),
+                    ("anchor", Some(note.anchor.clone())),
+                ]));
+            }
+            tokens.push(page_token("footnotes_end", vec![]));
+        }
+        Block::Tag { tag_type, meta } => tokens.push(PageToken { token_type: tag_type.clone(), meta: meta.clone() }),
+        Block::Container { tag_type, meta, blocks } => {
+            tokens.push(PageToken { token_type: format!("{}_start", tag_type), meta: meta.clone() });
+            blocks.iter().for_each(|b| block_to_page_tokens(b, tokens));
+            tokens.push(page_token(&format!("{}_end", tag_type), vec![]));
+        }
+    }
+}
+
+// blocks up to the end of the container named `end`, or to the end of the tokens at the top level.
+// a container that's never closed runs to the end of the tokens too
+fn parse_blocks(iter: &mut TokenIter, end: Option<&str>) -> Vec<Block> {
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut paragraph: Option<InlineBuilder> = None;
+
+    while let Some(token) = iter.next() {
+        let token_type = token.token_type.as_str();
+
+        if let Some(name) = container_end(token_type) {
+            if Some(name) == end { break; }
+            // the end of some other container, which never started
+            continue;
+        }
+
+        match token_type {
+            // tables are containers in .hmm, but by now they're rows rather than blocks
+            "table_start" => {
+                if let Some(builder) = paragraph.take() {
+                    blocks.push(builder.finish());
+                }
+                blocks.push(parse_table(token, iter));
+            }
+            // the block form of math, the inline one lives in paragraphs
+            "math" if token.meta.contains_key("display") => {
+                if let Some(builder) = paragraph.take() {
+                    blocks.push(builder.finish());
+                }
+                blocks.push(Block::Math { tex: meta(token, "math"), mathml: optional_meta(token, "mathml") });
+            }
+            t if container_start(t).is_some() => {
+                if let Some(builder) = paragraph.take() {
+                    blocks.push(builder.finish());
+                }
+                let name = container_start(t).unwrap();
+                let children = parse_blocks(iter, Some(name));
+                blocks.push(container_from(name, token, children));
+            }
+            "list_start" => {
+                if let Some(builder) = paragraph.take() {
+                    blocks.push(builder.finish());
+                }
+                blocks.push(parse_list(token, iter));
+            }
+            "footnotes_start" => {
+                if let Some(builder) = paragraph.take() {
+                    blocks.push(builder.finish());
+                }
+                blocks.push(parse_footnotes(iter));
+            }
+            // list and footnote pieces outside of where they belong
+            "item_start" | "item_end" | "list_end" | "footnote" | "footnotes_end"
+            | "row_start" | "row_end" | "cell_start" | "cell_end" => {}
+            "para_start" | "para_end" => {
+                // a stray end is dropped and a start without an end is closed here
+                if let Some(builder) = paragraph.take() {
+                    blocks.push(builder.finish());
+                }
+                if token_type == "para_start" {
+                    paragraph = Some(InlineBuilder::new());
+                }
+            }
+            t if is_block_token(t) => {
+                if let Some(builder) = paragraph.take() {
+                    blocks.push(builder.finish());
+                }
+                blocks.push(block_from(token));
+            }
+            // inline tokens outside of a paragraph start one
+            _ => paragraph.get_or_insert_with(InlineBuilder::new).push(token),
+        }
+    }
+
+    if let Some(builder) = paragraph {
+        blocks.push(builder.finish());
+    }
+    return tidy_blocks(blocks);
+}
+
+fn walk_inlines_mut(inlines: &mut [Inline], on_inline: &mut dyn FnMut(&mut Inline)) {
+    for inline in inlines.iter_mut() {
+        on_inline(inline);
+        if let Inline::Emphasis { children } | Inline::Strong { children } = inline {
+            walk_inlines_mut(children, on_inline);
+        }
+    }
+}
+
+// blocks before what's in them, and everything in the order it's read
+fn walk_blocks_mut(blocks: &mut [Block], on_block: &mut dyn FnMut(&mut Block), on_inline: &mut dyn FnMut(&mut Inline)) {
+    for block in blocks.iter_mut() {
+        on_block(block);
+        match block {
+            Block::Paragraph { children } => walk_inlines_mut(children, on_inline),
+            Block::List { items, .. } => {
+                for item in items.iter_mut() {
+                    walk_inlines_mut(&mut item.children, on_inline);
+                    walk_blocks_mut(&mut item.sublists, on_block, on_inline);
+                }
+            }
+            Block::Quote { blocks, .. } | Block::Container { blocks, .. } => walk_blocks_mut(blocks, on_block, on_inline),
+            Block::Table { head, body, .. } => {
+                for cell in head.iter_mut().chain(body.iter_mut()).flatten() {
+                    walk_inlines_mut(cell, on_inline);
+                }
+            }
+            Block::Gallery { images, .. } => walk_inlines_mut(images, on_inline),
+            Block::Figure { image } => walk_inlines_mut(std::slice::from_mut(image), on_inline),
+            _ => {}
+        }
+    }
+}
+
+impl Document {
+    pub fn from_page_tokens(tokens: &[PageToken]) -> Document {
+        let mut iter = tokens.iter().peekable();
+        return Document { blocks: parse_blocks(&mut iter, None) };
+    }
+
+    // every block however deeply it's nested, for the compiler's passes over the whole post
+    pub fn for_each_block_mut(&mut self, mut f: impl FnMut(&mut Block)) {
+        walk_blocks_mut(&mut self.blocks, &mut f, &mut |_| {});
+    }
+
+    pub fn for_each_inline_mut(&mut self, mut f: impl FnMut(&mut Inline)) {
+        walk_blocks_mut(&mut self.blocks, &mut |_| {}, &mut f);
+    }
+
+    // the legacy flat list, for templates and tools that haven't moved over yet
+    pub fn to_page_tokens(&self) -> Vec<PageToken> {
+        let mut tokens = Vec::new();
+        self.blocks.iter().for_each(|b| block_to_page_tokens(b, &mut tokens));
+        return tokens;
+    }
+}
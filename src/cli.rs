@@ -0,0 +1,182 @@
+use std::path::Path;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+use crate::config::{self, SiteConfig};
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn parse(args: Vec<&str>) -> Result<(SiteConfig, Command), String> {
+        return from_matches(&app().get_matches_from(args));
+    }
+
+    #[test]
+    fn flags_override_the_config_file() {
+        let dir = std::env::temp_dir().join(format!("simple-blog-cli-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config_file = dir.join("site.toml");
+        fs::write(&config_file, "port = 9000\ncontent_dir = \"posts/\"\n[site]\ntitle = \"From the file\"\n").unwrap();
+        let config_file = config_file.to_str().unwrap();
+
+        let (config, command) = parse(vec!["simple-blog", "--config", config_file, "--title", "From a flag"]).unwrap();
+        assert!(matches!(command, Command::Serve));
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.content_dir, "posts/");
+        assert_eq!(config.site.title, "From a flag");
+        assert!(!config.strict);
+
+        // global flags work after the subcommand too
+        let (config, command) = parse(vec!["simple-blog", "--config", config_file, "build", "--out", "site/", "--port", "9001", "--strict"]).unwrap();
+        assert!(matches!(command, Command::Build));
+        assert_eq!(config.output_dir, "site/");
+        assert_eq!(config.port, 9001);
+        assert!(config.strict);
+
+        assert_eq!(parse(vec!["simple-blog", "--config", config_file, "--port", "eighty"]).err(), Some(String::from("invalid port 'eighty'")));
+        assert!(parse(vec!["simple-blog", "--config", "missing.toml"]).is_err());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn subcommands_take_their_arguments() {
+        match parse(vec!["simple-blog", "new", "Hello, World!", "--tags", "rust, ,thinking"]).unwrap().1 {
+            Command::New { title, tags } => {
+                assert_eq!(title, "Hello, World!");
+                assert_eq!(tags, vec!["rust", "thinking"]);
+            }
+            _ => panic!("expected new"),
+        }
+        match parse(vec!["simple-blog", "fmt", "--check", "a.hmm", "b.hmm"]).unwrap().1 {
+            Command::Fmt { check, files } => {
+                assert!(check);
+                assert_eq!(files, vec!["a.hmm", "b.hmm"]);
+            }
+            _ => panic!("expected fmt"),
+        }
+        match parse(vec!["simple-blog", "export-markdown"]).unwrap().1 {
+            Command::ExportMarkdown { out_dir } => assert_eq!(out_dir, "./markdown/"),
+            _ => panic!("expected export-markdown"),
+        }
+        assert!(matches!(parse(vec!["simple-blog", "lint"]).unwrap().1, Command::Lint));
+    }
+}
+
+pub enum Command {
+    Serve,
+    Build,
+    Lint,
+    New { title: String, tags: Vec<String> },
+    Fmt { check: bool, files: Vec<String> },
+    ExportMarkdown { out_dir: String },
+}
+
+fn global_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("config").long("config").takes_value(true).global(true)
+            .help("A toml file with any of the settings below, defaults to site.toml"),
+        Arg::with_name("content-dir").long("content-dir").takes_value(true).global(true),
+        Arg::with_name("static-dir").long("static-dir").takes_value(true).global(true),
+        Arg::with_name("templates-dir").long("templates-dir").takes_value(true).global(true),
+        Arg::with_name("address").long("address").takes_value(true).global(true),
+        Arg::with_name("port").long("port").takes_value(true).global(true),
+        Arg::with_name("title").long("title").takes_value(true).global(true)
+            .help("The site title shown at the top of every page"),
+        Arg::with_name("strict").long("strict").global(true)
+            .help("Refuse to load content while it has any warnings or errors"),
+    ]
+}
+
+fn app<'a, 'b>() -> App<'a, 'b> {
+    App::new("simple-blog")
+        .about("A dead simple, tag based micro-blogging CMS")
+        .setting(AppSettings::VersionlessSubcommands)
+        .args(&global_args())
+        .subcommand(SubCommand::with_name("serve")
+            .about("Serve the site, the default when no subcommand is given"))
+        .subcommand(SubCommand::with_name("build")
+            .about("Render the whole site to static files")
+            .arg(Arg::with_name("out").long("out").takes_value(true)))
+        .subcommand(SubCommand::with_name("lint")
+            .about("Check the content tree for problems without starting the server"))
+        .subcommand(SubCommand::with_name("new")
+            .about("Create a new post")
+            .arg(Arg::with_name("post-title").required(true))
+            .arg(Arg::with_name("tags").long("tags").takes_value(true)
+                .help("Comma separated, e.g. --tags rust,thinking")))
+        .subcommand(SubCommand::with_name("fmt")
+            .about("Rewrite .hmm files in the canonical layout")
+            .arg(Arg::with_name("check").long("check")
+                .help("Only report unformatted files"))
+            .arg(Arg::with_name("files").multiple(true)))
+        .subcommand(SubCommand::with_name("export-markdown")
+            .about("Write a Markdown copy of every .hmm post")
+            .arg(Arg::with_name("out").default_value("./markdown/")))
+}
+
+// global args given after the subcommand only show up in the subcommand's matches
+fn value_of<'a>(matches: &'a ArgMatches, sub: Option<&'a ArgMatches>, name: &str) -> Option<&'a str> {
+    return sub.and_then(|s| s.value_of(name)).or(matches.value_of(name));
+}
+
+fn build_config(matches: &ArgMatches, name: &str, sub: Option<&ArgMatches>) -> Result<SiteConfig, String> {
+    let mut config = match value_of(matches, sub, "config") {
+        Some(path) => config::load_config_file(Path::new(path))?,
+        None if Path::new(config::DEFAULT_CONFIG_FILE).is_file() => {
+            config::load_config_file(Path::new(config::DEFAULT_CONFIG_FILE))?
+        }
+        None => SiteConfig::default(),
+    };
+
+    if let Some(dir) = value_of(matches, sub, "content-dir") { config.content_dir = dir.to_string(); }
+    if let Some(dir) = value_of(matches, sub, "static-dir") { config.static_dir = dir.to_string(); }
+    if let Some(dir) = value_of(matches, sub, "templates-dir") { config.templates_dir = dir.to_string(); }
+    if let Some(address) = value_of(matches, sub, "address") { config.address = address.to_string(); }
+    config.title_override = value_of(matches, sub, "title").map(String::from);
+    config.apply_site_overrides();
+    if let Some(port) = value_of(matches, sub, "port") {
+        config.port = port.parse().map_err(|_| format!("invalid port '{}'", port))?;
+    }
+    if matches.is_present("strict") || sub.map(|s| s.is_present("strict")).unwrap_or(false) {
+        config.strict = true;
+    }
+    if name == "build" {
+        if let Some(out) = sub.and_then(|s| s.value_of("out")) { config.output_dir = out.to_string(); }
+    }
+
+    return Ok(config);
+}
+
+pub fn parse_args() -> Result<(SiteConfig, Command), String> {
+    return from_matches(&app().get_matches());
+}
+
+fn from_matches(matches: &ArgMatches) -> Result<(SiteConfig, Command), String> {
+    let (name, sub) = matches.subcommand();
+    let config = build_config(matches, name, sub)?;
+
+    let command = match (name, sub) {
+        ("build", _) => Command::Build,
+        ("lint", _) => Command::Lint,
+        ("new", Some(sub)) => Command::New {
+            title: sub.value_of("post-title").unwrap().to_string(),
+            tags: sub.value_of("tags")
+                .map(|t| t.split(',').map(|x| x.trim().to_string()).filter(|x| !x.is_empty()).collect())
+                .unwrap_or_default(),
+        },
+        ("fmt", Some(sub)) => Command::Fmt {
+            check: sub.is_present("check"),
+            files: sub.values_of("files").map(|f| f.map(String::from).collect()).unwrap_or_default(),
+        },
+        ("export-markdown", Some(sub)) => Command::ExportMarkdown {
+            out_dir: sub.value_of("out").unwrap().to_string(),
+        },
+        _ => Command::Serve,
+    };
+
+    return Ok((config, command));
+}
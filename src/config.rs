@@ -0,0 +1,249 @@
+use std::fs;
+use std::path::Path;
+use std::sync::RwLock;
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+// site.toml by default, or whatever was passed with --config. command line flags win over what's
+// in the file. the [site] table is what templates see, and it's the only part that gets hot
+// reloaded since the rest only matters at startup
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_settings_keep_their_defaults() {
+        let config: SiteConfig = toml::from_str("port = 9000\n[site]\ntitle = \"Test\"\n[site.pagination]\nper_page = 5\n").unwrap();
+
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.site.title, "Test");
+        assert_eq!(config.site.pagination.per_page, 5);
+        assert_eq!(config.content_dir, "./content/");
+        assert_eq!(config.site.base_url, "http://localhost:8000");
+        assert_eq!(config.site.feed.max_items, 20);
+        assert!(config.site.features.search);
+        assert!(config.config_file.is_none());
+    }
+
+    #[test]
+    fn config_files_are_loaded() {
+        let dir = std::env::temp_dir().join(format!("simple-blog-config-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("site.toml"), "strict = true\n[site.features]\nsearch = false\n").unwrap();
+        fs::write(dir.join("broken.toml"), "port = \"not a number\"\n").unwrap();
+
+        let config = load_config_file(&dir.join("site.toml")).unwrap();
+        assert!(config.strict);
+        assert!(!config.site.features.search);
+        assert!(config.site.features.git_history);
+        assert_eq!(config.config_file, Some(dir.join("site.toml").to_str().unwrap().to_string()));
+
+        assert!(load_config_file(&dir.join("broken.toml")).unwrap_err().starts_with("couldn't parse"));
+        assert!(load_config_file(&dir.join("missing.toml")).unwrap_err().starts_with("couldn't read"));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn reloads_keep_command_line_flags() {
+        let dir = std::env::temp_dir().join(format!("simple-blog-reload-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("site.toml"), "[site]\ntitle = \"From the file\"\n[site.pagination]\nper_page = 3\n").unwrap();
+
+        let mut config = SiteConfig { title_override: Some(String::from("From a flag")), ..SiteConfig::default() };
+        config.apply_site_overrides();
+
+        reload_site(&mut config, load_config_file(&dir.join("site.toml")).unwrap().site);
+        assert_eq!(config.site.title, "From a flag");
+        assert_eq!(config.site.pagination.per_page, 3);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct NavLink {
+    pub text: String,
+    pub url: String,
+}
+
+impl Default for NavLink {
+    fn default() -> NavLink {
+        NavLink { text: String::new(), url: String::new() }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct FeedSettings {
+    pub enabled: bool,
+    pub max_items: usize,
+}
+
+impl Default for FeedSettings {
+    fn default() -> FeedSettings {
+        FeedSettings { enabled: true, max_items: 20 }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PaginationSettings {
+    // 0 puts every post on the index
+    pub per_page: usize,
+}
+
+impl Default for PaginationSettings {
+    fn default() -> PaginationSettings {
+        PaginationSettings { per_page: 0 }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct FeatureToggles {
+    pub night_mode: bool,
+    pub lightbox: bool,
+    pub git_history: bool,
+    pub search: bool,
+}
+
+impl Default for FeatureToggles {
+    fn default() -> FeatureToggles {
+        FeatureToggles { night_mode: true, lightbox: true, git_history: true, search: true }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SiteSettings {
+    pub title: String,
+    // used for absolute links, e.g. in the feed
+    pub base_url: String,
+    pub author: String,
+    pub nav: Vec<NavLink>,
+    pub feed: FeedSettings,
+    pub pagination: PaginationSettings,
+    pub features: FeatureToggles,
+    // set while rendering the static build, there's no server to submit the search form to then
+    #[serde(skip_deserializing)]
+    pub static_build: bool,
+}
+
+impl Default for SiteSettings {
+    fn default() -> SiteSettings {
+        SiteSettings {
+            title: String::from("Max's Thoughts & Feelings"),
+            base_url: String::from("http://localhost:8000"),
+            author: String::new(),
+            nav: Vec::new(),
+            feed: FeedSettings::default(),
+            pagination: PaginationSettings::default(),
+            features: FeatureToggles::default(),
+            static_build: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SiteConfig {
+    pub content_dir: String,
+    pub static_dir: String,
+    pub templates_dir: String,
+    pub output_dir: String,
+    pub address: String,
+    pub port: u16,
+    pub strict: bool,
+    // how long the content monitor waits for things to settle before reloading
+    pub debounce_secs: u64,
+    pub site: SiteSettings,
+    // where this config came from, if anywhere
+    #[serde(skip)]
+    pub config_file: Option<String>,
+    // --title, kept so it still wins after the [site] table is reloaded
+    #[serde(skip)]
+    pub title_override: Option<String>,
+}
+
+impl SiteConfig {
+    // puts the command line flags back over the [site] table
+    pub fn apply_site_overrides(&mut self) {
+        if let Some(title) = &self.title_override {
+            self.site.title = title.clone();
+        }
+    }
+}
+
+impl Default for SiteConfig {
+    fn default() -> SiteConfig {
+        SiteConfig {
+            content_dir: String::from("./content/"),
+            static_dir: String::from("static/"),
+            templates_dir: String::from("templates/"),
+            output_dir: String::from("./build/"),
+            address: String::from("0.0.0.0"),
+            port: 8000,
+            strict: false,
+            debounce_secs: 10,
+            site: SiteSettings::default(),
+            config_file: None,
+            title_override: None,
+        }
+    }
+}
+
+pub const DEFAULT_CONFIG_FILE: &str = "site.toml";
+
+lazy_static! {
+    static ref SITE_CONFIG: RwLock<SiteConfig> = RwLock::new(SiteConfig::default());
+}
+
+pub fn load_config_file(path: &Path) -> Result<SiteConfig, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+    let mut config: SiteConfig = toml::from_str(&contents)
+        .map_err(|e| format!("couldn't parse {}: {}", path.display(), e))?;
+    config.config_file = Some(path.to_str().unwrap().to_string());
+    return Ok(config);
+}
+
+pub fn set_config(config: SiteConfig) {
+    let mut current = SITE_CONFIG.write().unwrap();
+    *current = config;
+}
+
+pub fn get_config() -> SiteConfig {
+    let config = SITE_CONFIG.read().unwrap();
+    return config.clone();
+}
+
+// picks up edits to the [site] table of the config file without a restart
+pub fn reload_site_settings() -> Result<(), String> {
+    let path = match get_config().config_file {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+
+    let reloaded = load_config_file(Path::new(&path))?;
+    reload_site(&mut SITE_CONFIG.write().unwrap(), reloaded.site);
+    println!("Reloaded site settings from {}", path);
+    return Ok(());
+}
+
+// swaps in a freshly loaded [site] table, keeping what was set from outside the file
+fn reload_site(config: &mut SiteConfig, site: SiteSettings) {
+    let static_build = config.site.static_build;
+    config.site = site;
+    config.site.static_build = static_build;
+    config.apply_site_overrides();
+}
+
+// whether templates_dir has a template with this name, e.g. "post_wide"
+pub fn template_exists(name: &str) -> bool {
+    let config = get_config();
+    return Path::new(&config.templates_dir).join(format!("{}.html.tera", name)).is_file();
+}
@@ -10,10 +10,20 @@ use std::path::{Path, PathBuf};
 use std::str::CharIndices;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use chrono::{TimeZone, Utc};
 use rocket::http::ext::IntoCollection;
 use walkdir::WalkDir;
 
+use crate::ast::{self, Align, Block, Document, Footnote, Inline, ListItem, TableRow};
+use crate::config;
+use crate::diagnostics::{self, Diagnostic, Severity};
+use crate::git_history::{self, FileHistory};
+use crate::markdown_import;
+use crate::math;
+use crate::search;
 use crate::site_cache;
+use crate::tables;
+use crate::tag_handlers::{self, TagContext};
 use crate::tokens::*;
 use crate::tokens::Token::*;
 
@@ -72,15 +82,167 @@ mod tests {
             EOF,
         ]);
 
+        assert_eq!(tokens.len(), 3);
         assert!(matches!(tokens[0], Tag{..}));
-        assert!(matches!(tokens[1], StartParagraph));
-        assert!(matches!(tokens[2], Span(_)));
-        assert!(matches!(tokens[3], EndParagraph));
-        assert!(matches!(tokens[4], StartParagraph));
-        assert!(matches!(tokens[5], Span(_)));
-        assert!(matches!(tokens[6], Tag{..}));
-        assert!(matches!(tokens[7], Span(_)));
-        assert!(matches!(tokens[8], EndParagraph));
+        assert!(matches!(&tokens[1], Paragraph(children) if matches!(children[..], [Span(_)])));
+        assert!(matches!(&tokens[2], Paragraph(children) if matches!(children[..], [Span(_), Tag{..}, Span(_)])));
+    }
+
+    #[test]
+    fn hashes_that_arent_tags_stay_text() {
+        let tokens = lex_content("C# and F# #[url:x.com|text:x]#".to_string());
+        assert_eq!(tokens, vec![
+            Span("C# and F# ".into()),
+            Tag("url".into(), "url:x.com|text:x".into()),
+            Span("#".into()),
+            EOF,
+        ]);
+    }
+
+    #[test]
+    fn list_items_nest_by_indentation() {
+        let tokens = run_passes(lex_content("Intro\n- one #[url:x.com|text:x]\n  1. inner\n- two\n\nAfter".to_string()));
+
+        assert_eq!(tokens.len(), 3);
+        assert!(matches!(&tokens[0], Paragraph(_)));
+        match &tokens[1] {
+            List(false, items) => {
+                assert_eq!(items.len(), 2);
+                assert!(matches!(items[0][..], [Span(_), Tag{..}, List(true, _)]));
+                assert!(matches!(items[1][..], [Span(_)]));
+            }
+            other => panic!("expected a list, got {:?}", other),
+        }
+        assert!(matches!(&tokens[2], Paragraph(_)));
+    }
+
+    #[test]
+    fn quotes_hold_paragraphs() {
+        let tokens = run_passes(lex_content("Before\n#[quote|cite:Someone]\nOne #[url:x.com|text:x]\n\nTwo\n#[/quote]\nAfter".to_string()));
+
+        assert_eq!(tokens.len(), 3);
+        match &tokens[1] {
+            Container(tag_type, _, children) => {
+                assert_eq!(&**tag_type, "quote");
+                assert!(matches!(children[..], [Paragraph(_), Paragraph(_)]));
+            }
+            other => panic!("expected a quote, got {:?}", other),
+        }
+        assert!(matches!(&tokens[2], Paragraph(children) if matches!(children[..], [Span(_)])));
+    }
+
+    #[test]
+    fn unbalanced_containers_are_errors() {
+        let contents = "#[/quote]\n#[quote]\nNever closed";
+        let (tokens, offsets, _) = lex_content_with_offsets(contents);
+        let diagnostics = validate_tags(&tokens, &offsets, contents, &"test.hmm".to_string());
+
+        let positions: Vec<_> = diagnostics.iter().map(|d| (d.severity, d.line)).collect();
+        assert_eq!(positions, vec![(Severity::Error, 1), (Severity::Error, 2)]);
+    }
+
+    #[test]
+    fn footnotes_are_numbered_and_collected() {
+        let document = compile_hmm(&"One#[fn:first]\n\n- two#[fn:second]".to_string(), &"fn.hmm".to_string(), &mut Vec::new());
+        let tokens = add_footnotes(document, &"hash".to_string()).to_page_tokens();
+
+        let refs: Vec<_> = tokens.iter().filter(|t| t.token_type == "fn").map(|t| t.meta["anchor"].as_str()).collect();
+        assert_eq!(refs, vec!["hash-1", "hash-2"]);
+
+        let types: Vec<_> = tokens.iter().rev().take(5).map(|t| t.token_type.as_str()).collect();
+        assert_eq!(types, vec!["footnotes_end", "footnote", "footnote", "footnotes_start", "list_end"]);
+        assert_eq!(tokens[tokens.len() - 2].meta["text"], "second");
+    }
+
+    #[test]
+    fn galleries_get_site_wide_names() {
+        let contents = "#[gallery]\n#[image:a.png|alt:A] stray words\n#[url:x.com|text:x]\n#[image:b.png|alt:B]\n#[/gallery]\n\n\
+            #[image:c.png|alt:C|gallery:boards]\n\n#[image:d.png|alt:D|gallery:1]".to_string();
+        let mut diagnostics = Vec::new();
+        let tokens = add_galleries(compile_hmm(&contents, &"g.hmm".to_string(), &mut diagnostics), &"hash".to_string()).to_page_tokens();
+
+        let names: Vec<_> = tokens.iter().filter(|t| t.token_type == "image").map(|t| t.meta["gallery"].as_str()).collect();
+        assert_eq!(names, vec!["hash-gallery-1", "hash-gallery-1", "hash-named-boards", "hash-named-1"]);
+        let positions: Vec<_> = diagnostics.iter().map(|d| (d.line, d.column)).collect();
+        assert_eq!(positions, vec![(2, 22), (3, 1)]);
+        assert!(diagnostics[0].message.contains("'stray words'"));
+    }
+
+    #[test]
+    fn pipe_rows_become_tables() {
+        let tokens = run_passes(lex_content("#[table|caption:Results]\n| name | time |\n|:--|--:|\n| see #[url:x.com|text:x] | 1 |\n| | 2 |\n#[/table]\nAfter".to_string()));
+
+        assert_eq!(tokens.len(), 2);
+        match &tokens[0] {
+            Table(_, align, header_rows, rows) => {
+                assert_eq!(align, &vec!["left".to_string(), "right".to_string()]);
+                assert_eq!(*header_rows, 1);
+                assert_eq!(rows.len(), 3);
+                assert!(matches!(rows[1][0][..], [Span(ref s), Tag(..)] if s == "see "));
+                assert!(rows[2][0].is_empty());
+            }
+            other => panic!("expected a table, got {:?}", other),
+        }
+        assert!(matches!(&tokens[1], Paragraph(_)));
+    }
+
+    #[test]
+    fn math_errors_point_into_the_file() {
+        let contents = "Inline #[math: x^{2]\n#[math]\n  \\frac{a}\n#[/math]\n#[math:\\sum_i x_i]";
+        let mut diagnostics = Vec::new();
+        let tokens = compile_hmm(&contents.to_string(), &"math.hmm".to_string(), &mut diagnostics).to_page_tokens();
+
+        let positions: Vec<_> = diagnostics.iter().map(|d| (d.line, d.column)).collect();
+        assert_eq!(positions, vec![(1, 18), (3, 3)]);
+
+        let types: Vec<_> = tokens.iter().map(|t| t.token_type.as_str()).collect();
+        assert_eq!(types, vec!["para_start", "span", "math", "para_end", "math", "para_start", "math", "para_end"]);
+        assert_eq!(tokens[4].meta["math"], "\\frac{a}");
+        assert!(tokens[4].meta.contains_key("display"));
+    }
+
+    #[test]
+    fn math_output_cant_be_written_by_hand() {
+        let hash = "hash".to_string();
+        let ctx = TagContext { root: Path::new("test"), title_hash: &hash, block: false };
+        let contents = "#[math:x|mathml:<script>alert(1)</script>|display]\n\n#[math]\ny\n#[/math]".to_string();
+        let mut diagnostics = Vec::new();
+        let document = compile_hmm_with(&contents, &"math.hmm".to_string(), Some(&ctx), &mut diagnostics);
+
+        assert!(diagnostics.iter().any(|d| d.severity == Severity::Error && d.message.contains("'display' is worked out by the compiler")));
+        match &document.blocks[..] {
+            [Block::Paragraph { children }, Block::Math { mathml: Some(display), .. }] => {
+                assert!(matches!(&children[..], [Inline::Math { mathml: Some(inline), .. }]
+                    if inline.contains("display=\"inline\"") && !inline.contains("<script>")));
+                assert!(display.contains("display=\"block\""));
+            }
+            other => panic!("expected inline and display math, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unreadable_tables_point_at_their_tag() {
+        let dir = std::env::temp_dir().join(format!("simple-blog-table-test-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(dir.join("post")).unwrap();
+        fs::write(dir.join("post/ok.csv"), "a,b\n1,2\n").unwrap();
+        fs::write(dir.join("secret.csv"), "a\n1\n").unwrap();
+
+        let hash = "hash".to_string();
+        let root = dir.join("post");
+        let ctx = TagContext { root: &root, title_hash: &hash, block: false };
+        let contents = "#[table:ok.csv]\n\n#[table:missing.csv]\n\n#[table:../secret.csv]".to_string();
+        let mut diagnostics = Vec::new();
+        let document = compile_hmm_with(&contents, &"t.hmm".to_string(), Some(&ctx), &mut diagnostics);
+
+        assert!(matches!(document.blocks[0], Block::Table { .. }));
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (3, 1));
+        assert!(diagnostics[0].message.contains("missing.csv"));
+        assert_eq!((diagnostics[1].line, diagnostics[1].column), (5, 1));
+        assert!(diagnostics[1].message.contains("outside the post's directory"));
+
+        fs::remove_dir_all(&dir).ok();
     }
 
     #[test]
@@ -95,24 +257,84 @@ mod tests {
             }
         ];
 
-        let page_tokens = create_file_links(page_tokens, Path::new("test"), &"hash".to_string());
+        let hash = "hash".to_string();
+        let ctx = TagContext { root: Path::new("test"), title_hash: &hash, block: false };
+        let page_tokens = create_file_links(page_tokens, &ctx);
+
+        assert_eq!("/site-content/hash/image.png", page_tokens.get(0).unwrap().meta.get("image").unwrap());
+
+        // .hmm posts have theirs resolved on the way into the document
+        let document = compile_hmm_with(&"#[image:image.png|alt:A]".to_string(), &"test.hmm".to_string(), Some(&ctx), &mut Vec::new());
+        assert!(matches!(&document.blocks[..], [Block::Paragraph { children }]
+            if matches!(&children[..], [Inline::Image { src, .. }] if src == "/site-content/hash/image.png")));
+    }
+
+    #[test]
+    fn title_keys_are_kept_and_bad_timestamps_reported() {
+        let mut diagnostics = Vec::new();
+        let document = compile_hmm(&"#[title:Hi|timestamp:yesterday|mood:calm]".to_string(), &"title.hmm".to_string(), &mut diagnostics);
+
+        assert!(diagnostics.iter().any(|d| d.severity == Severity::Error && d.message.contains("'timestamp' expects a number")));
+        match &document.blocks[..] {
+            [Block::Title { timestamp, extra, .. }] => {
+                assert_eq!(*timestamp, None);
+                assert_eq!(extra.get("mood").unwrap(), "calm");
+            }
+            other => panic!("expected a title, got {:?}", other),
+        }
+        assert_eq!(document.to_page_tokens()[0].meta["mood"], "calm");
+    }
+
+    #[test]
+    fn tag_problems_point_at_their_source() {
+        let contents = "#[imgae:go.png]\nSome text then #[url:x.com]";
+        let (tokens, offsets, _) = lex_content_with_offsets(contents);
+        let diagnostics = validate_tags(&tokens, &offsets, contents, &"test.hmm".to_string());
+
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (1, 1));
+        assert_eq!(diagnostics[1].severity, Severity::Error);
+        assert_eq!((diagnostics[1].line, diagnostics[1].column), (2, 16));
+    }
+
+    #[test]
+    fn registered_block_tags_end_paragraphs() {
+        // the registry is global and tests run in parallel, so the name is one no other test uses
+        struct Divider;
+        impl tag_handlers::TagHandler for Divider {
+            fn name(&self) -> &'static str { "test_only_divider" }
+            fn is_block(&self) -> bool { true }
+        }
+        tag_handlers::register_tag_handler(std::sync::Arc::new(Divider));
 
-        assert_eq!("site-content/hash/image.png", page_tokens.get(0).unwrap().meta.get("image").unwrap())
+        let tokens: Vec<_> = create_paragraphs(vec![
+            Span("This is a paragraph!".into()),
+            Tag("test_only_divider".into(), "test_only_divider".into()),
+            Span("This is another paragraph".into()),
+            EOF,
+        ]);
+
+        assert_eq!(tokens.len(), 3);
+        assert!(matches!(&tokens[0], Paragraph(children) if matches!(children[..], [Span(_)])));
+        assert!(matches!(tokens[1], Tag{..}));
+        assert!(matches!(&tokens[2], Paragraph(children) if matches!(children[..], [Span(_)])));
     }
 }
 
-const NON_PARAGRAPH_TAG_TYPES: &'static [&'static str] = &[
-    &"header",
-    &"title",
-];
+#[derive(Clone)]
+pub struct CompileOptions {
+    // any warning or error fails the whole load instead of just being reported
+    pub strict: bool,
+}
 
-pub fn load_site_content() {
-    let entries = WalkDir::new("./content/")
+pub fn load_site_content(content_dir: &Path, options: &CompileOptions) -> Result<(), Vec<Diagnostic>> {
+    let entries = WalkDir::new(content_dir)
         .into_iter()
         .filter_map(|e| e.ok())
         .map(|e| e.into_path())
         .filter(|e| e.is_file())
-        .filter(|e| e.extension().unwrap() == "hmm")
+        .filter(|e| is_content_file(e))
         .collect::<Vec<PathBuf>>();
 
     let content_files = entries.iter()
@@ -120,10 +342,19 @@ pub fn load_site_content() {
         .collect::<Vec<_>>();
     println!("Processing content files: {}", content_files.join(", "));
 
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
     let results = entries.into_iter()
-        .map(|file| compile_content(file))
+        .map(|file| compile_content(file, &mut diagnostics))
         .collect::<Vec<_>>();
 
+    for d in diagnostics.iter() {
+        println!("{}", d);
+    }
+    if options.strict && !diagnostics.is_empty() {
+        println!("Strict mode: not loading site content, {} problem(s) found", diagnostics.len());
+        return Err(diagnostics);
+    }
+
     println!("{:?}", results);
     println!("Loading pages into global cache...");
     for page in results {
@@ -131,57 +362,249 @@ pub fn load_site_content() {
         let p = page.unwrap();
         site_cache::cache_page(p.file_name.clone(), p);
     }
+    search::rebuild_index();
+    return Ok(());
 }
 
+// groups runs of inline tokens into paragraphs, block tags and blank lines end them
 fn create_paragraphs(tokens: Vec<Token>) -> Vec<Token> {
     if !tokens.has_eof_token() { panic!("Invalid yo"); }
 
     let mut iter = tokens.into_iter();
     let mut new_tokens: Vec<Token> = Vec::new();
 
-    let mut is_paragraph = false;
+    let mut paragraph: Vec<Token> = Vec::new();
     while let Some(token) = iter.next() {
         match token {
             Span(_) => {
-                if !is_paragraph {
-                    new_tokens.push(StartParagraph);
-                    is_paragraph = true;
+                paragraph.push(token);
+            }
+            Tag(ref tag_type, ref tag_string) if tag_handlers::opens_container(tag_type, &parse_tag_meta(tag_string)) => {
+                end_paragraph(&mut paragraph, &mut new_tokens);
+                if let Tag(tag_type, tag_string) = token {
+                    let children = take_container(&tag_type, &mut iter);
+                    new_tokens.push(Container(tag_type, tag_string, create_paragraphs(children)));
                 }
-                new_tokens.push(token.clone());
+            }
+            Tag(ref tag_type, _) if tag_handlers::closed_container(tag_type).is_some() => {
+                // a closing tag without an opening one, validate_tags has already complained
+                end_paragraph(&mut paragraph, &mut new_tokens);
             }
             Tag(ref tag_type, _) => {
-                if is_paragraph && NON_PARAGRAPH_TAG_TYPES.contains(&tag_type.as_ref()) {
+                if tag_handlers::is_block(tag_type) {
                     // we've noticed a paragraph and now there's a tag type which should end it
-                    is_paragraph = false;
-                    new_tokens.push(EndParagraph);
-                } else if !is_paragraph && !NON_PARAGRAPH_TAG_TYPES.contains(&tag_type.as_ref()) {
-                    new_tokens.push(StartParagraph);
-                    is_paragraph = true;
+                    end_paragraph(&mut paragraph, &mut new_tokens);
+                    new_tokens.push(token);
+                } else {
+                    paragraph.push(token);
                 }
-                new_tokens.push(token.clone());
             }
-            Newline => {
-                if is_paragraph {
-                    new_tokens.push(EndParagraph);
-                    is_paragraph = false;
-                }
+            List(..) | Table(..) => {
+                end_paragraph(&mut paragraph, &mut new_tokens);
+                new_tokens.push(token);
+            }
+            Newline | EOF => {
+                end_paragraph(&mut paragraph, &mut new_tokens);
                 // discard newlines
             }
-            EOF => {
-                if is_paragraph {
-                    new_tokens.push(EndParagraph);
-                    is_paragraph = false;
+            _ => {
+                new_tokens.push(token);
+            }
+        }
+    }
+
+    return new_tokens;
+}
+
+// the body of a `#[table]` is pipe rows, one per line, so this runs before anything joins lines
+// together. a `|---|:-:|` row ends the header and says how each column is aligned
+fn create_tables(tokens: Vec<Token>) -> Vec<Token> {
+    let mut iter = tokens.into_iter().peekable();
+    let mut new_tokens: Vec<Token> = Vec::new();
+
+    while let Some(token) = iter.next() {
+        match token {
+            Tag(ref tag_type, ref tag_string) if &**tag_type == "table" && tag_handlers::opens_container(tag_type, &parse_tag_meta(tag_string)) => {
+                let mut lines: Vec<Vec<Token>> = vec![Vec::new()];
+                while let Some(next) = iter.peek() {
+                    match next {
+                        EOF => break,
+                        Tag(t, _) if &**t == "/table" => {
+                            iter.next();
+                            break;
+                        }
+                        Newline => {
+                            iter.next();
+                            lines.push(Vec::new());
+                        }
+                        _ => lines.last_mut().unwrap().push(iter.next().unwrap()),
+                    }
+                }
+                if let Tag(_, tag_string) = token {
+                    new_tokens.push(table_from_lines(tag_string, lines));
+                }
+            }
+            _ => new_tokens.push(token),
+        }
+    }
+
+    return new_tokens;
+}
+
+fn is_blank_cell(cell: &Vec<Token>) -> bool {
+    return cell.iter().all(|t| matches!(t, Span(text) if text.trim().is_empty()));
+}
+
+// `| a | b #[url:x.com|text:x] |` -> [[a], [b, url]], a cell can hold any inline tokens
+fn split_cells(line: Vec<Token>) -> Vec<Vec<Token>> {
+    let mut cells: Vec<Vec<Token>> = vec![Vec::new()];
+    for token in line {
+        match token {
+            Span(text) => {
+                for (i, part) in text.split('|').enumerate() {
+                    if i > 0 { cells.push(Vec::new()); }
+                    cells.last_mut().unwrap().push(Span(part.to_string()));
                 }
             }
-            _ => {
-                new_tokens.push(token.clone());
+            Tag(..) => cells.last_mut().unwrap().push(token),
+            _ => {}
+        }
+    }
+
+    // the pipes at either end of a row don't start or end a cell
+    if cells.first().map(is_blank_cell).unwrap_or(false) { cells.remove(0); }
+    if cells.last().map(is_blank_cell).unwrap_or(false) { cells.pop(); }
+
+    return cells.into_iter().map(|cell| {
+        let last = cell.len().saturating_sub(1);
+        cell.into_iter().enumerate().filter_map(|(i, token)| match token {
+            Span(text) => {
+                let text = if i == 0 { text.trim_start() } else { &text };
+                let text = if i == last { text.trim_end() } else { text };
+                if text.is_empty() { None } else { Some(Span(text.to_string())) }
+            }
+            _ => Some(token),
+        }).collect()
+    }).collect();
+}
+
+fn table_from_lines(tag_string: String, lines: Vec<Vec<Token>>) -> Token {
+    let mut rows: Vec<Vec<Vec<Token>>> = Vec::new();
+    let mut align: Vec<String> = Vec::new();
+    let mut header_rows = 0;
+
+    for line in lines {
+        let cells = split_cells(line);
+        if cells.iter().all(is_blank_cell) { continue; }
+
+        let separator: Option<Vec<&str>> = cells.iter().map(|cell| match &cell[..] {
+            [Span(text)] => tables::separator_alignment(text),
+            _ => None,
+        }).collect();
+        match separator {
+            Some(separator) => {
+                // only the first one counts, any others are dropped
+                if align.is_empty() {
+                    header_rows = rows.len();
+                    align = separator.into_iter().map(String::from).collect();
+                }
+            }
+            None => rows.push(cells),
+        }
+    }
+
+    return Table(tag_string, align, header_rows, rows);
+}
+
+// runs of list item lines become lists, a blank line or any other line ends them. items indented
+// further than the one before them are nested inside it
+fn create_lists(tokens: Vec<Token>) -> Vec<Token> {
+    let mut iter = tokens.into_iter().peekable();
+    let mut new_tokens: Vec<Token> = Vec::new();
+    let mut items: Vec<(bool, usize, Vec<Token>)> = Vec::new();
+
+    while let Some(token) = iter.next() {
+        match token {
+            ListItem(ordered, indent) => {
+                let mut item: Vec<Token> = Vec::new();
+                while let Some(next) = iter.peek() {
+                    if matches!(next, Newline | EOF) { break; }
+                    item.push(iter.next().unwrap());
+                }
+                items.push((ordered, indent, item));
+
+                // the newline ending this item
+                if let Some(Newline) = iter.peek() { iter.next(); }
+                if !matches!(iter.peek(), Some(ListItem(..))) {
+                    new_tokens.extend(nest_lists(&items));
+                    items.clear();
+                }
             }
+            _ => new_tokens.push(token),
         }
     }
 
     return new_tokens;
 }
 
+fn nest_lists(items: &[(bool, usize, Vec<Token>)]) -> Vec<Token> {
+    let mut lists: Vec<Token> = Vec::new();
+    let indent = match items.first() {
+        Some((_, indent, _)) => *indent,
+        None => return lists,
+    };
+
+    let mut i = 0;
+    while i < items.len() {
+        let (ordered, _, item) = &items[i];
+
+        // anything indented further belongs to this item
+        let mut end = i + 1;
+        while end < items.len() && items[end].1 > indent { end += 1; }
+
+        let mut children = item.clone();
+        children.extend(nest_lists(&items[i + 1..end]));
+
+        // switching between `-` and `1.` at the same level starts a new list
+        match lists.last_mut() {
+            Some(List(list_ordered, list_items)) if list_ordered == ordered => list_items.push(children),
+            _ => lists.push(List(*ordered, vec![children])),
+        }
+        i = end;
+    }
+
+    return lists;
+}
+
+// everything up to the tag closing this container, with containers of the same type inside it
+// closing first. one that's never closed takes the rest of the file
+fn take_container(tag_type: &str, iter: &mut std::vec::IntoIter<Token>) -> Vec<Token> {
+    let mut children: Vec<Token> = Vec::new();
+    let mut depth = 0;
+
+    while let Some(token) = iter.next() {
+        match token {
+            Tag(ref t, _) if &**t == tag_type => depth += 1,
+            Tag(ref t, _) if tag_handlers::closed_container(t) == Some(tag_type) => {
+                if depth == 0 { break; }
+                depth -= 1;
+            }
+            EOF => break,
+            _ => {}
+        }
+        children.push(token);
+    }
+
+    children.push(EOF);
+    return children;
+}
+
+fn end_paragraph(paragraph: &mut Vec<Token>, tokens: &mut Vec<Token>) {
+    if !paragraph.is_empty() {
+        tokens.push(Paragraph(paragraph.split_off(0)));
+    }
+}
+
 // this just asserts w/e rules we need to know if a token is valid or not
 trait TokenStreamValidator {
     fn has_eof_token(&self) -> bool;
@@ -233,135 +656,543 @@ fn merge_spans(tokens: Vec<Token>) -> Vec<Token> {
     return new_tokens;
 }
 
-fn compile_content(file: PathBuf) -> Result<SiteContent, &'static str> {
+fn is_markdown(file: &Path) -> bool {
+    return file.extension().map(|ext| ext == "md").unwrap_or(false);
+}
 
-    // make sure it's a file
-    let content_meta = fs::metadata(file.clone())
-        .expect("Something went wrong reading the file");
+pub fn is_content_file(file: &Path) -> bool {
+    return file.extension().map(|ext| ext == "hmm" || ext == "md").unwrap_or(false);
+}
 
-    // open palm slam that shit into ram
-    let contents = fs::read_to_string(file.clone())
-        .expect("Something went wrong reading the file");
+// a post as a document. without a context nothing is resolved against where it lives on disk, the
+// way the linter and the markdown export want it
+pub fn compile_document(file: &Path, contents: &String, file_name: &String, ctx: Option<&TagContext>, diagnostics: &mut Vec<Diagnostic>) -> Option<Document> {
+    if is_markdown(file) {
+        // markdown without front matter isn't a post, e.g. content/readme.md. the import still
+        // makes flat tokens
+        let tokens = markdown_import::markdown_to_page_tokens(contents, file_name, diagnostics)?;
+        let tokens = match ctx {
+            Some(ctx) => create_file_links(tokens, ctx),
+            None => tokens,
+        };
+        return Some(Document::from_page_tokens(&tokens));
+    }
+    return Some(compile_hmm_with(contents, file_name, ctx, diagnostics));
+}
+
+pub fn compile_hmm(contents: &String, file_name: &String, diagnostics: &mut Vec<Diagnostic>) -> Document {
+    return compile_hmm_with(contents, file_name, None, diagnostics);
+}
 
+fn compile_hmm_with(contents: &String, file_name: &String, ctx: Option<&TagContext>, diagnostics: &mut Vec<Diagnostic>) -> Document {
     // lex it
-    let tokens = lex_content(contents);
+    let (tokens, tag_offsets, unclosed) = lex_content_with_offsets(contents);
+    if let Some(offset) = unclosed {
+        let (line, column) = diagnostics::source_position(contents, offset);
+        diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            file: file_name.clone(),
+            line,
+            column,
+            message: String::from("#[ is never closed with a ]"),
+        });
+    }
+    diagnostics.extend(validate_tags(&tokens, &tag_offsets, contents, file_name));
+    diagnostics.extend(validate_math(&tokens, &tag_offsets, contents, file_name));
+    diagnostics.extend(validate_galleries(&tokens, &tag_offsets, contents, file_name));
+    if let Some(ctx) = ctx {
+        diagnostics.extend(validate_tables(&tokens, &tag_offsets, contents, file_name, ctx.root));
+    }
 
-    // do a few passes on the data to massage it into the right shape and generate new tokens,
-    // remove redundant ones, etc.
+    let tokens = run_passes(tokens);
+    return Document { blocks: convert_to_document(tokens, ctx) };
+}
+
+// do a few passes on the data to massage it into the right shape and generate new tokens,
+// remove redundant ones, etc.
+pub fn run_passes(tokens: Vec<Token>) -> Vec<Token> {
+    let tokens = create_tables(tokens);
+    let tokens = create_lists(tokens);
     let tokens = remove_redundant_newlines(tokens);
     let tokens = merge_spans(tokens);
     let tokens = create_paragraphs(tokens);
+    return tokens;
+}
 
-    let page_tokens = convert_to_page_tokens(tokens);
+fn compile_content(file: PathBuf, diagnostics: &mut Vec<Diagnostic>) -> Result<SiteContent, &'static str> {
 
-    let local_page_path = file.parent().unwrap();
+    // make sure it's a file
+    let content_meta = fs::metadata(file.clone())
+        .expect("Something went wrong reading the file");
+
+    // open palm slam that shit into ram
+    let contents = fs::read_to_string(file.clone())
+        .expect("Something went wrong reading the file");
 
     let file_name: String = file.to_str().unwrap().into();
+    let local_page_path = file.parent().unwrap();
+
     let mut s = DefaultHasher::new();
     file_name.hash(&mut s);
     let title_hash = s.finish().to_string();
-    let page_tokens = create_file_links(page_tokens, local_page_path, &title_hash);
+
+    // every tag handler gets a chance to rewrite its tag now that we know where the page lives,
+    // this is where relative image paths get mapped to their site-content/ urls
+    let ctx = TagContext { root: local_page_path, title_hash: &title_hash, block: false };
+    let document = match compile_document(&file, &contents, &file_name, Some(&ctx), diagnostics) {
+        Some(document) => document,
+        None => return Err("markdown file has no front matter"),
+    };
 
     site_cache::create_link(&title_hash, local_page_path);
 
+    // optional, only available when the content lives in a git repo
+    let history = if config::get_config().site.features.git_history {
+        git_history::file_history(&file)
+    } else {
+        None
+    };
+    let document = add_history_info(document, &history, &title_hash);
+    let document = add_permalink(document, &title_hash);
+    let document = add_footnotes(document, &title_hash);
+    let document = add_galleries(document, &title_hash);
+
     let result = SiteContent {
-        timestamp: decide_timestamp(&page_tokens, &content_meta),
+        timestamp: decide_timestamp(&document, &content_meta, &history),
+        template: decide_template(&document),
+        slug: decide_slug(&document, &file),
         file_name: file_name.clone(),
+        page_hash: title_hash,
         title: file_name,
-        page_tokens,
+        created: history.as_ref().map(|h| h.created),
+        updated: history.as_ref().map(|h| h.updated),
+        revisions: history.map(|h| h.revisions).unwrap_or_default(),
+        page_tokens: document.to_page_tokens(),
+        document,
     };
     return Ok(result);
 }
 
-fn create_file_links(tokens: Vec<PageToken>, root: &Path, title_hash: &String) -> Vec<PageToken> {
-    let mut new_tokens: Vec<PageToken> = Vec::new();
+// the first header of a post links through to the post's own page
+fn add_permalink(document: Document, title_hash: &String) -> Document {
+    let mut document = document;
+    let mut linked = false;
+    document.for_each_block_mut(|block| {
+        if let Block::Header { link, .. } = block {
+            if !linked {
+                *link = Some(format!("/post/{}", title_hash));
+                linked = true;
+            }
+        }
+    });
+    return document;
+}
 
-    let mut iter = tokens.into_iter();
-    while let Some(token) = iter.next() {
-        match token {
-            PageToken { token_type, mut meta } if token_type == "image" => {
-                if let Some(url_path) = meta.get("image") {
-                    // leave fully qualified uris alone
-                    if !url_path.contains("://") {
-                        let mut os_path = root.to_path_buf().clone();
-                        os_path.push(url_path);
+// numbers every footnote in the order they appear and lists them all at the end of the post. anchors
+// carry the post's hash so they stay unique when several posts share the index page
+fn add_footnotes(document: Document, title_hash: &String) -> Document {
+    let mut document = document;
+    let mut notes: Vec<Footnote> = Vec::new();
 
-                        let site_path = format!("site-content/{}/{}", title_hash, url_path);
+    document.for_each_inline_mut(|inline| {
+        let text = match inline {
+            Inline::Tag { tag_type, meta } if tag_type == "fn" => meta.get("fn").cloned().unwrap_or_default(),
+            _ => return,
+        };
+        let number = notes.len() + 1;
+        let anchor = format!("{}-{}", title_hash, number);
+        notes.push(Footnote { number, anchor: anchor.clone(), text: text.clone() });
+        *inline = Inline::FootnoteRef { number, anchor, text };
+    });
 
-                        meta.insert("image".into(), site_path);
-                    }
+    if !notes.is_empty() {
+        document.blocks.push(Block::Footnotes { notes });
+    }
+    return document;
+}
+
+// glightbox steps through every image with the same `data-gallery`, so names have to be unique across
+// the site, the index page shows several posts at once. a `#[gallery]` is numbered and its images
+// join it, images with a `gallery:` key join the post's gallery of that name. the two get different
+// prefixes so `gallery:1` doesn't end up in the first `#[gallery]`
+fn add_galleries(document: Document, title_hash: &String) -> Document {
+    let mut document = document;
+    let mut galleries = 0;
+
+    document.for_each_inline_mut(|inline| {
+        if let Inline::Image { gallery: Some(key), .. } = inline {
+            *key = format!("{}-named-{}", title_hash, key);
+        }
+    });
+    // a `#[gallery]` doesn't have a name until now, grids of keyed images are named after the key
+    document.for_each_block_mut(|block| {
+        if let Block::Gallery { name, images } = block {
+            *name = if name.is_empty() {
+                galleries += 1;
+                format!("{}-gallery-{}", title_hash, galleries)
+            } else {
+                format!("{}-named-{}", title_hash, name)
+            };
+            for image in images.iter_mut() {
+                if let Inline::Image { gallery, .. } = image {
+                    *gallery = Some(name.clone());
                 }
-                new_tokens.push(PageToken { token_type, meta });
             }
-            _ => { new_tokens.push(token); }
         }
+    });
+    return document;
+}
+
+fn title_block(document: &Document) -> Option<&Block> {
+    return document.blocks.iter().find(|b| matches!(b, Block::Title { .. }));
+}
+
+// `slug:` on the title tag wins, otherwise it's made from the file name
+pub fn decide_slug(document: &Document, file: &Path) -> String {
+    if let Some(Block::Title { slug: Some(slug), .. }) = title_block(document) {
+        return slugify(slug);
     }
 
-    return new_tokens;
+    let stem = file.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+    return slugify(stem);
 }
 
-fn convert_to_page_tokens(tokens: Vec<Token>) -> Vec<PageToken> {
-    let mut page_tokens: Vec<PageToken> = Vec::new();
-    let mut token_iter = tokens.into_iter().peekable();
-    loop {
-        let token = token_iter.next();
-        if let Some(token) = token {
-            match token {
-                Tag(tag_type, tag_string) => {
-                    let splits: Vec<&str> = tag_string.split('|').collect();
-                    let final_tags: Vec<(String, String)> = splits.into_iter().map(|tg| {
-                        let components: Vec<&str> = tg.split(':').collect();
-                        if components.len() > 2 { panic!("Too many ':'s in tag component"); }
-
-                        let key = components.get(0).unwrap().to_string();
-                        let val = components.get(1).unwrap_or(&"<empty>").to_string();
-                        (key, val)
-                    }).collect();
-
-                    page_tokens.push(PageToken {
-                        token_type: tag_type.into(),
-                        meta: final_tags.into_iter().collect(),
-                    })
-                }
-                Span(text) => {
-                    page_tokens.push(PageToken {
-                        token_type: "span".to_string(),
-                        meta: vec![("text".to_string(), text.clone())].into_iter().collect(),
-                    })
+// "My First_Post!" -> "my-first-post"
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for ch in text.trim().chars() {
+        if ch.is_ascii_alphanumeric() {
+            slug.push(ch.to_ascii_lowercase());
+        } else if !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    return slug.trim_matches('-').to_string();
+}
+
+// posts can pick an alternative layout with a `template:` key on their title tag
+fn decide_template(document: &Document) -> Option<String> {
+    if let Some(Block::Title { template: Some(template), .. }) = title_block(document) {
+        return Some(template.trim().to_string());
+    }
+    return None;
+}
+
+// posts which have been edited since they were first committed get an "updated" block right after
+// their header, linking to the diff of the latest revision. the `.diff` is so the static build's
+// copy of it is served as text
+fn add_history_info(document: Document, history: &Option<FileHistory>, title_hash: &String) -> Document {
+    let history = match history {
+        Some(h) if h.revisions.len() > 1 => h,
+        _ => return document,
+    };
+
+    let latest = history.revisions.first().unwrap();
+    let updated = Block::Updated {
+        date: Utc.timestamp(history.updated as i64, 0).format("%B %e, %Y").to_string(),
+        diff: format!("/history/{}/{}.diff", title_hash, latest.id),
+    };
+
+    let insert_at = document.blocks.iter()
+        .position(|b| matches!(b, Block::Header { .. }))
+        .map(|i| i + 1)
+        .unwrap_or(0);
+
+    let mut document = document;
+    document.blocks.insert(insert_at, updated);
+    return document;
+}
+
+// a tag's token, run through its handler when the page's context is known
+fn expand_tag(token: PageToken, ctx: Option<&TagContext>) -> Vec<PageToken> {
+    return match (ctx, tag_handlers::get_tag_handler(&token.token_type)) {
+        (Some(ctx), Some(handler)) => handler.expand(token, ctx),
+        _ => vec![token],
+    };
+}
+
+// the same for page tokens which didn't come from the compiler, i.e. imported markdown
+fn create_file_links(tokens: Vec<PageToken>, ctx: &TagContext) -> Vec<PageToken> {
+    return tokens.into_iter().flat_map(|token| expand_tag(token, Some(ctx))).collect();
+}
+
+// `key:value|flag|key:value` -> map, only the first ':' splits so values can hold urls
+pub fn parse_tag_meta(tag_string: &str) -> HashMap<String, String> {
+    return tag_string.split('|').map(|tg| {
+        let mut components = tg.splitn(2, ':');
+
+        let key = components.next().unwrap().trim().to_string();
+        let val = components.next().map(|v| v.trim()).unwrap_or("<empty>").to_string();
+        (key, val)
+    }).collect();
+}
+
+// checks every tag against its schema, before the later passes throw away where things came from
+fn validate_tags(tokens: &Vec<Token>, tag_offsets: &Vec<usize>, contents: &str, file_name: &String) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    let tags = tokens.iter().filter_map(|t| match t {
+        Tag(tag_type, tag_string) => Some((tag_type, tag_string)),
+        _ => None,
+    });
+
+    // containers which are still open, and where they were opened
+    let mut open: Vec<(&str, usize, usize)> = Vec::new();
+
+    for ((tag_type, tag_string), offset) in tags.zip(tag_offsets.iter()) {
+        let (line, column) = diagnostics::source_position(contents, *offset);
+
+        if let Some(name) = tag_handlers::closed_container(tag_type) {
+            match open.iter().rposition(|(open_name, _, _)| *open_name == name) {
+                Some(position) => { open.truncate(position); }
+                None => diagnostics.push(Diagnostic {
+                    severity: Severity::Error,
+                    file: file_name.clone(),
+                    line,
+                    column,
+                    message: format!("#[/{}] doesn't close anything", name),
+                }),
+            }
+            continue;
+        }
+        let meta = parse_tag_meta(tag_string);
+        if tag_handlers::opens_container(tag_type, &meta) {
+            open.push((tag_type, line, column));
+        }
+
+        for (severity, message) in tag_handlers::check_tag(tag_type, &meta) {
+            diagnostics.push(Diagnostic {
+                severity,
+                file: file_name.clone(),
+                line,
+                column,
+                message,
+            });
+        }
+    }
+
+    for (name, line, column) in open {
+        diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            file: file_name.clone(),
+            line,
+            column,
+            message: format!("#[{}] is never closed with #[/{}]", name, name),
+        });
+    }
+
+    return diagnostics;
+}
+
+// only images go in a gallery, any other tag or text directly inside one is left out of the page
+fn validate_galleries(tokens: &Vec<Token>, tag_offsets: &Vec<usize>, contents: &str, file_name: &String) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut offsets = tag_offsets.iter();
+    let mut open: Vec<&str> = Vec::new();
+    // where the last tag ended, text is looked for from there
+    let mut after_tag = 0;
+
+    let mut warn = |offset: usize, message: String| {
+        let (line, column) = diagnostics::source_position(contents, offset);
+        diagnostics.push(Diagnostic { severity: Severity::Warning, file: file_name.clone(), line, column, message });
+    };
+
+    for token in tokens {
+        let in_gallery = open.last() == Some(&"gallery");
+        match token {
+            Tag(tag_type, tag_string) => {
+                let offset = *offsets.next().unwrap();
+                after_tag = offset + tag_string.len() + 3;
+
+                if let Some(name) = tag_handlers::closed_container(tag_type) {
+                    if let Some(position) = open.iter().rposition(|open_name| *open_name == name) {
+                        open.truncate(position);
+                    }
+                    continue;
                 }
-                StartParagraph => {
-                    page_tokens.push(PageToken {
-                        token_type: "para_start".to_string(),
-                        meta: HashMap::new(),
-                    })
+                if in_gallery && &tag_type[..] != "image" {
+                    warn(offset, format!("only images go in a #[gallery], this '{}' tag is left out", tag_type));
                 }
-                EndParagraph => {
-                    page_tokens.push(PageToken {
-                        token_type: "para_end".to_string(),
-                        meta: HashMap::new(),
-                    })
+                if tag_handlers::opens_container(tag_type, &parse_tag_meta(tag_string)) {
+                    open.push(tag_type);
                 }
-                _ => {}
             }
-        } else {
-            break;
+            Span(text) if in_gallery && !text.trim().is_empty() => {
+                let text = text.trim();
+                let offset = contents[after_tag..].find(text).map(|i| after_tag + i).unwrap_or(after_tag);
+                warn(offset, format!("only images go in a #[gallery], the text '{}' is left out", text));
+            }
+            _ => {}
+        }
+    }
+
+    return diagnostics;
+}
+
+// a table's csv file can only be looked for once we know where the post lives
+fn validate_tables(tokens: &Vec<Token>, tag_offsets: &Vec<usize>, contents: &str, file_name: &String, root: &Path) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    let tags = tokens.iter().filter_map(|t| match t {
+        Tag(tag_type, tag_string) => Some((tag_type, tag_string)),
+        _ => None,
+    });
+
+    for ((tag_type, tag_string), offset) in tags.zip(tag_offsets.iter()) {
+        if &**tag_type != "table" { continue; }
+        let file = match parse_tag_meta(tag_string).remove("table") {
+            Some(file) if file != "<empty>" => file,
+            _ => continue,
+        };
+
+        if let Err(message) = tables::read_csv_file(root, &file) {
+            let (line, column) = diagnostics::source_position(contents, *offset);
+            diagnostics.push(Diagnostic { severity: Severity::Error, file: file_name.clone(), line, column, message });
+        }
+    }
+
+    return diagnostics;
+}
+
+// formulas are converted later on, this checks them while we still know where they are in the file
+fn validate_math(tokens: &Vec<Token>, tag_offsets: &Vec<usize>, contents: &str, file_name: &String) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut offsets = tag_offsets.iter();
+
+    let mut iter = tokens.iter().peekable();
+    while let Some(token) = iter.next() {
+        let (tag_type, tag_string) = match token {
+            Tag(tag_type, tag_string) => (tag_type, tag_string),
+            _ => continue,
+        };
+        let offset = *offsets.next().unwrap();
+        if &**tag_type != "math" { continue; }
+
+        // where the TeX starts in the file, straight after the tag for the block form
+        let (tex, start, display) = match iter.peek() {
+            Some(Raw(body)) => (body.as_str(), offset + tag_string.len() + 3, true),
+            _ => {
+                let colon = match tag_string.find(':') {
+                    Some(colon) => colon,
+                    None => continue,
+                };
+                let value = tag_string[colon + 1..].split('|').next().unwrap();
+                let leading = value.len() - value.trim_start().len();
+                (value, offset + 2 + colon + 1 + leading, false)
+            }
+        };
+
+        if let Err(e) = math::tex_to_mathml(tex.trim_start(), display) {
+            let skipped = tex.len() - tex.trim_start().len();
+            let start = if display { start + skipped } else { start };
+            let (line, column) = diagnostics::source_position(contents, start + e.offset);
+            diagnostics.push(Diagnostic {
+                severity: Severity::Error,
+                file: file_name.clone(),
+                line,
+                column,
+                message: format!("in formula: {}", e.message),
+            });
+        }
+    }
+
+    return diagnostics;
+}
+
+fn tag_token(tag_type: &str, tag_string: &str) -> PageToken {
+    return PageToken { token_type: tag_type.to_string(), meta: parse_tag_meta(tag_string) };
+}
+
+// a tag's blocks. handlers still hand back flat tokens, e.g. a table read from a csv file
+fn tag_blocks(token: PageToken, ctx: Option<&TagContext>) -> Vec<Block> {
+    return Document::from_page_tokens(&expand_tag(token, ctx)).blocks;
+}
+
+fn convert_inlines(tokens: Vec<Token>, ctx: Option<&TagContext>) -> Vec<Inline> {
+    let mut inlines: Vec<Inline> = Vec::new();
+    for token in tokens {
+        match token {
+            Span(text) => inlines.push(Inline::Text { text }),
+            Tag(tag_type, tag_string) => {
+                inlines.extend(expand_tag(tag_token(&tag_type, &tag_string), ctx).iter().map(ast::inline_from));
+            }
+            _ => {}
         }
     }
-    return page_tokens;
+    return inlines;
+}
+
+// an item's text and tags are its children, any lists in it are nested under it
+fn convert_list(ordered: bool, items: Vec<Vec<Token>>, ctx: Option<&TagContext>) -> Block {
+    let items = items.into_iter().map(|item| {
+        let (sublists, inline): (Vec<Token>, Vec<Token>) = item.into_iter().partition(|t| matches!(t, List(..)));
+        ListItem { children: convert_inlines(inline, ctx), sublists: convert_to_document(sublists, ctx) }
+    }).collect();
+    return Block::List { ordered, items };
 }
 
-fn decide_timestamp(tokens: &Vec<PageToken>, content_meta: &Metadata) -> u128 {
-    for x in tokens {
-        if x.token_type.eq("title") {
-            if let Some(ts) = x.meta.get("timestamp") {
-                return ts.parse::<u128>().unwrap();
+// the passes' tokens as the document's blocks. with a context every tag goes through its handler on
+// the way in
+fn convert_to_document(tokens: Vec<Token>, ctx: Option<&TagContext>) -> Vec<Block> {
+    let mut blocks: Vec<Block> = Vec::new();
+    for token in tokens {
+        match token {
+            Paragraph(children) => blocks.push(Block::Paragraph { children: convert_inlines(children, ctx) }),
+            List(ordered, items) => blocks.push(convert_list(ordered, items, ctx)),
+            Table(tag_string, align, header_rows, rows) => {
+                let mut head: Vec<TableRow> = rows.into_iter()
+                    .map(|row| row.into_iter().map(|cell| convert_inlines(cell, ctx)).collect())
+                    .collect();
+                let body = head.split_off(header_rows.min(head.len()));
+                blocks.push(Block::Table {
+                    caption: parse_tag_meta(&tag_string).remove("caption").filter(|c| c != "<empty>"),
+                    align: align.iter().map(|a| Align::parse(a)).collect(),
+                    head,
+                    body,
+                });
+            }
+            // a raw body goes under the head key, the same place `#[math:...]` keeps its value. the
+            // handler is told it's a block, and its tokens are flagged as display afterwards so the
+            // tree tells them apart from the inline form
+            Container(tag_type, tag_string, children) if matches!(children[..], [Raw(_)]) => {
+                let mut token = tag_token(&tag_type, &tag_string);
+                if let Some(Raw(body)) = children.into_iter().next() {
+                    token.meta.insert(tag_type.to_string(), body.trim().to_string());
+                }
+                let ctx = ctx.map(|ctx| TagContext { block: true, ..*ctx });
+                let mut tokens = expand_tag(token, ctx.as_ref());
+                for token in tokens.iter_mut() {
+                    token.meta.insert("display".to_string(), "<empty>".to_string());
+                }
+                blocks.extend(Document::from_page_tokens(&tokens).blocks);
+            }
+            Container(tag_type, tag_string, children) => {
+                let start = tag_token(&tag_type, &tag_string);
+                blocks.push(ast::container_from(&tag_type, &start, convert_to_document(children, ctx)));
             }
+            Tag(tag_type, tag_string) => blocks.extend(tag_blocks(tag_token(&tag_type, &tag_string), ctx)),
+            _ => {}
         }
     }
+    return ast::tidy_blocks(blocks);
+}
+
+// a timestamp on the title tag which isn't a number has already been reported, it's treated as
+// missing here
+fn decide_timestamp(document: &Document, content_meta: &Metadata, history: &Option<FileHistory>) -> u128 {
+    if let Some(Block::Title { timestamp: Some(ts), .. }) = title_block(document) {
+        return *ts as u128;
+    }
+
+    // the first commit of a file is a far better guess than the filesystem, which resets on checkout
+    if let Some(history) = history {
+        return history.created;
+    }
 
     if let Ok(ts) = content_meta.created() {
         if let Ok(ts) = ts.duration_since(UNIX_EPOCH) {
-            return ts.as_millis();
+            // seconds, like title and git timestamps and everything that formats them
+            return ts.as_secs() as u128;
         }
     }
 
@@ -402,47 +1233,115 @@ fn finish_span(span: &mut String, tokens: &mut Vec<Token>) {
     }
 }
 
-fn lex_tag(span: &mut String, char_iter: &mut CharIndices, tokens: &mut Vec<Token>) {
-    let (_, next_ch) = char_iter.next().expect("Unexpected EOF");
-    if next_ch == '[' {
-        finish_span(span, tokens);
+// returns false for a `#[` which is never closed, it's kept as text
+fn lex_tag(span: &mut String, char_iter: &mut CharIndices, tokens: &mut Vec<Token>) -> bool {
+    // a `#` that doesn't start a tag is just text, like in "C#"
+    if !char_iter.as_str().starts_with('[') {
+        span.push('#');
+        return true;
+    }
 
-        let mut value = String::new();
-        loop {
-            let (_, next_ch) = char_iter.next().expect("Unexpected EOF");
-            if next_ch == ']' { // stop building a tag
-                break;
-            }
-            value.push(next_ch);
+    let value = match char_iter.as_str()[1..].find(']') {
+        Some(end) => char_iter.as_str()[1..end + 1].to_string(),
+        None => {
+            char_iter.next();
+            span.push_str("#[");
+            return false;
         }
+    };
+    // skip the `[`, the value and the `]`
+    for _ in 0..value.chars().count() + 2 { char_iter.next(); }
+    finish_span(span, tokens);
 
-        // grab the first token to guess at the type
-        let s: Vec<&str> = value.split('|').collect();
-        if s.len() > 0 {
-            let i: Vec<&str> = s[0].split(':').collect();
-            let tag_val = if i.len() > 0 { i[0] } else { s[0] };
-            tokens.push(Tag(tag_val.into(), value));
-        } else {
-            tokens.push(Tag(value.clone().as_str().into(), value));
-        }
+    // grab the first token to guess at the type
+    let s: Vec<&str> = value.split('|').collect();
+    if s.len() > 0 {
+        let i: Vec<&str> = s[0].split(':').collect();
+        let tag_val = if i.len() > 0 { i[0] } else { s[0] };
+        tokens.push(Tag(tag_val.into(), value));
+    } else {
+        tokens.push(Tag(value.clone().as_str().into(), value));
     }
+    return true;
+}
+
+// everything up to the closing tag, which is left for the lexer to pick up like any other tag.
+// without one the body runs to the end of the file
+fn lex_raw_body(tag_type: &str, char_iter: &mut CharIndices) -> String {
+    let rest = char_iter.as_str();
+    let end = rest.find(&format!("#[/{}]", tag_type)).unwrap_or(rest.len());
+    let body = rest[..end].to_string();
+    for _ in 0..body.chars().count() { char_iter.next(); }
+    return body;
+}
+
+// `- ` or `1. ` after any amount of indentation, a tab counts as 4 spaces. returns whether it's
+// ordered, the indentation and how many chars the whole marker and the spaces after it take up
+fn list_marker(line: &str) -> Option<(bool, usize, usize)> {
+    let is_space = |c: &char| *c == ' ' || *c == '\t';
+    let indent_chars = line.chars().take_while(is_space).count();
+    let indent = line.chars().take(indent_chars).map(|c| if c == '\t' { 4 } else { 1 }).sum();
+    let rest = &line[indent_chars..];
+
+    let digits = rest.chars().take_while(|c| c.is_ascii_digit()).count();
+    let (ordered, marker) = if rest.starts_with("- ") {
+        (false, 1)
+    } else if digits > 0 && rest[digits..].starts_with(". ") {
+        (true, digits + 1)
+    } else {
+        return None;
+    };
+
+    let spaces = rest[marker..].chars().take_while(is_space).count();
+    return Some((ordered, indent, indent_chars + marker + spaces));
 }
 
-fn lex_content(contents: String) -> Vec<Token> {
+pub fn lex_content(contents: String) -> Vec<Token> {
+    let (tokens, _, _) = lex_content_with_offsets(&contents);
+    return tokens;
+}
+
+// same as lex_content, but also hands back the byte offset of every tag in the order they appear,
+// and of a `#[` which never gets closed
+fn lex_content_with_offsets(contents: &str) -> (Vec<Token>, Vec<usize>, Option<usize>) {
     let mut tokens: Vec<Token> = Vec::new();
+    let mut tag_offsets: Vec<usize> = Vec::new();
+    let mut unclosed: Option<usize> = None;
     let mut char_iter = contents.char_indices();
     let mut current_span = String::new();
+    let mut line_start = true;
 
     loop {
+        if line_start {
+            line_start = false;
+            if let Some((ordered, indent, marker_len)) = list_marker(char_iter.as_str()) {
+                tokens.push(ListItem(ordered, indent));
+                for _ in 0..marker_len { char_iter.next(); }
+            }
+        }
+
         // seek a token we know what to do with
         match char_iter.next() {
-            Some((_, ch)) => {
+            Some((offset, ch)) => {
                 // todo: use match
                 if ch == '\n' {       // -- note a newline
                     finish_span(&mut current_span, &mut tokens);
                     tokens.push(Newline);
+                    line_start = true;
                 } else if ch == '#' { // -- note a tag
-                    lex_tag(&mut current_span, &mut char_iter, &mut tokens);
+                    let token_count = tokens.len();
+                    if !lex_tag(&mut current_span, &mut char_iter, &mut tokens) {
+                        unclosed = Some(offset);
+                    }
+                    if tokens.len() > token_count {
+                        if let Some(Tag(tag_type, tag_string)) = tokens.last() {
+                            tag_offsets.push(offset);
+                            if tag_handlers::has_raw_body(tag_type, &parse_tag_meta(tag_string)) {
+                                let body = lex_raw_body(tag_type, &mut char_iter);
+                                tokens.push(Raw(body));
+                            }
+                        }
+                    }
                 } else {
                     if ch != '\n' && ch != '\r' {
                         current_span.push(ch);
@@ -463,5 +1362,5 @@ fn lex_content(contents: String) -> Vec<Token> {
 
     //println!("{:?}", tokens);
 
-    return tokens;
-}
\ No newline at end of file
+    return (tokens, tag_offsets, unclosed);
+}
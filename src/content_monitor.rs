@@ -1,26 +1,67 @@
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::channel;
 use std::time::Duration;
 
 use notify::{DebouncedEvent, RecursiveMode, watcher, Watcher};
-use notify::DebouncedEvent::Write;
 
-use crate::content_compiler;
+use crate::config;
+use crate::content_compiler::{self, CompileOptions};
+
+// editors tend to replace files rather than write to them, so anything that lands on the config
+// file counts as a change
+fn is_config_file(path: &PathBuf, config_file: &Option<PathBuf>) -> bool {
+    return match config_file {
+        Some(config_file) => fs::canonicalize(path).map(|p| &p == config_file).unwrap_or(false),
+        None => false,
+    };
+}
 
 // https://docs.rs/notify/4.0.15/notify/
-pub fn start_monitor() {
+pub fn start_monitor(content_dir: PathBuf, options: CompileOptions) {
     let (tx, rx) = channel();
 
-    let mut watcher = watcher(tx, Duration::from_secs(10)).unwrap();
+    let debounce = config::get_config().debounce_secs;
+    let mut watcher = watcher(tx, Duration::from_secs(debounce)).unwrap();
+
+    // absolute paths so events can be told apart from ones for the config file. recursive, posts
+    // live in subdirectories like content/2020_november/
+    let watched_content = fs::canonicalize(&content_dir).unwrap_or(content_dir.clone());
+    watcher.watch(&watched_content, RecursiveMode::Recursive).unwrap();
 
-    watcher.watch("./content", RecursiveMode::NonRecursive).unwrap();
+    // watch the directory holding the config file, watching the file itself stops working once an
+    // editor swaps it out
+    let config_file = config::get_config().config_file.and_then(|f| fs::canonicalize(f).ok());
+    if let Some(parent) = config_file.as_ref().and_then(|f| f.parent()) {
+        watcher.watch(parent, RecursiveMode::NonRecursive).unwrap();
+    }
 
     loop {
         match rx.recv() {
-            Ok(DebouncedEvent::Write(path)) => {
-                content_compiler::load_site_content();
+            Ok(DebouncedEvent::Write(path)) | Ok(DebouncedEvent::Create(path)) | Ok(DebouncedEvent::Rename(_, path))
+                if is_config_file(&path, &config_file) => {
+                // a broken config keeps the previous settings around, same as broken content
+                if let Err(e) = config::reload_site_settings() {
+                    println!("Config reload failed, keeping the previous settings: {}", e);
+                }
+            }
+            // new, moved and deleted posts change the site as much as edited ones
+            Ok(DebouncedEvent::Write(path)) | Ok(DebouncedEvent::Create(path)) | Ok(DebouncedEvent::Remove(path))
+                if path.starts_with(&watched_content) => {
+                reload_content(&content_dir, &options);
+            }
+            Ok(DebouncedEvent::Rename(from, to)) if from.starts_with(&watched_content) || to.starts_with(&watched_content) => {
+                reload_content(&content_dir, &options);
             }
             Err(e) => println!("watch error: {:?}", e),
             _ => {}
         }
     }
-}
\ No newline at end of file
+}
+
+fn reload_content(content_dir: &Path, options: &CompileOptions) {
+    // in strict mode a broken edit leaves the previously loaded content in place
+    if content_compiler::load_site_content(content_dir, options).is_err() {
+        println!("Content reload failed, keeping the previous version");
+    }
+}
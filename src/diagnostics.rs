@@ -0,0 +1,47 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+// a problem found while compiling content, pointing back at where it came from. line and column
+// are 1-based, a line of 0 means the problem is with the file as a whole
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn for_file(severity: Severity, file: &String, message: String) -> Diagnostic {
+        Diagnostic { severity, file: file.clone(), line: 0, column: 0, message }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        // problems with a whole file rather than a spot in it have no line
+        if self.line == 0 {
+            return write!(f, "{}: {}: {}", self.file, severity, self.message);
+        }
+        write!(f, "{}:{}:{}: {}: {}", self.file, self.line, self.column, severity, self.message)
+    }
+}
+
+// turns a byte offset into a 1-based line & column
+pub fn source_position(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let column = before[line_start..].chars().count() + 1;
+    return (line, column);
+}
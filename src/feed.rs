@@ -0,0 +1,115 @@
+use chrono::{TimeZone, Utc};
+
+use crate::config::SiteSettings;
+use crate::render::{self, escape_html};
+use crate::site_cache;
+use crate::tokens::SiteContent;
+
+// an atom feed of the newest posts, links are absolute using the configured base url
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content_compiler;
+
+    fn post(hash: &str, contents: &str, timestamp: u128) -> SiteContent {
+        let document = content_compiler::compile_hmm(&contents.to_string(), &format!("{}.hmm", hash), &mut Vec::new());
+        SiteContent {
+            file_name: format!("{}.hmm", hash),
+            page_hash: hash.to_string(),
+            slug: hash.to_string(),
+            title: format!("{}.hmm", hash),
+            template: None,
+            timestamp,
+            created: None,
+            updated: None,
+            revisions: Vec::new(),
+            page_tokens: document.to_page_tokens(),
+            document,
+        }
+    }
+
+    #[test]
+    fn newest_posts_make_the_feed() {
+        let mut site = SiteSettings::default();
+        site.base_url = String::from("https://blog.test/");
+        site.feed.max_items = 1;
+
+        let feed = feed_for(vec![
+            post("old", "#[title:Old]\n\nGone", 1605073000),
+            post("new", "#[title:Fish & Chips]\n\nFirst <b>para</b>\n\nSecond", 1605073292),
+        ], &site);
+
+        assert!(feed.contains("<feed xmlns=\"http://www.w3.org/2005/Atom\" xml:base=\"https://blog.test/\">"));
+        assert!(feed.contains("<updated>2020-11-11T05:41:32+00:00</updated>"));
+        assert!(feed.contains("<title>Fish &amp; Chips</title>"));
+        assert!(feed.contains("<link href=\"https://blog.test/post/new\"/>"));
+        assert!(feed.contains("<summary>First &lt;b&gt;para&lt;/b&gt;</summary>"));
+        assert!(!feed.contains("Old"));
+        assert!(feed.ends_with("</entry>\n</feed>\n"));
+    }
+}
+
+fn rfc3339(timestamp: u128) -> String {
+    return Utc.timestamp(timestamp as i64, 0).to_rfc3339();
+}
+
+// the text of the first paragraph, good enough for feed readers
+fn summary(page: &SiteContent) -> String {
+    return page.page_tokens.iter()
+        .skip_while(|t| t.token_type != "para_start")
+        .take_while(|t| t.token_type != "para_end")
+        .filter(|t| t.token_type == "span")
+        .filter_map(|t| t.meta.get("text"))
+        .map(|t| t.as_str())
+        .collect::<Vec<&str>>()
+        .join(" ");
+}
+
+fn entry(page: &SiteContent, base_url: &str) -> String {
+    let link = format!("{}/post/{}", base_url, page.page_hash);
+    let updated = page.updated.unwrap_or(page.timestamp);
+
+    return format!(
+        "  <entry>\n    <title>{}</title>\n    <link href=\"{}\"/>\n    <id>{}</id>\n    <published>{}</published>\n    <updated>{}</updated>\n    <summary>{}</summary>\n    <content type=\"html\">{}</content>\n  </entry>\n",
+        escape_html(&page.display_title()),
+        escape_html(&link),
+        escape_html(&link),
+        rfc3339(page.timestamp),
+        rfc3339(updated),
+        escape_html(&summary(page)),
+        escape_html(&render::render_document(&page.document)),
+    );
+}
+
+pub fn atom_feed(site: &SiteSettings) -> String {
+    return feed_for(site_cache::get_all_site_content(), site);
+}
+
+fn feed_for(mut pages: Vec<SiteContent>, site: &SiteSettings) -> String {
+    let base_url = site.base_url.trim_end_matches('/');
+
+    pages.sort();
+    pages.truncate(site.feed.max_items);
+
+    let updated = pages.iter().map(|p| p.updated.unwrap_or(p.timestamp)).max().unwrap_or(0);
+
+    let mut feed = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    // post bodies use relative links, xml:base resolves them against the site
+    feed.push_str(&format!("<feed xmlns=\"http://www.w3.org/2005/Atom\" xml:base=\"{}/\">\n", escape_html(base_url)));
+    feed.push_str(&format!("  <title>{}</title>\n", escape_html(&site.title)));
+    feed.push_str(&format!("  <link href=\"{}/\"/>\n", escape_html(base_url)));
+    feed.push_str(&format!("  <link rel=\"self\" href=\"{}/feed.xml\"/>\n", escape_html(base_url)));
+    feed.push_str(&format!("  <id>{}/</id>\n", escape_html(base_url)));
+    feed.push_str(&format!("  <updated>{}</updated>\n", rfc3339(updated)));
+    if !site.author.is_empty() {
+        feed.push_str(&format!("  <author><name>{}</name></author>\n", escape_html(&site.author)));
+    }
+
+    for page in pages.iter() {
+        feed.push_str(&entry(page, base_url));
+    }
+
+    feed.push_str("</feed>\n");
+    return feed;
+}
@@ -0,0 +1,288 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use walkdir::WalkDir;
+
+use crate::content_compiler;
+use crate::tag_handlers;
+use crate::tokens::Token;
+use crate::tokens::Token::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSY: &str = "#[title: A place to dump my thoughts |tags:thinking|timestamp:1605073292]\n\n\n\
+        #[header|date:Today|text:A Modest Header]\n\
+        Some text about C# that\nwraps over lines.\n\n\n\n\
+        A #[url: test.com |text:test link] in the middle.\n\n\n\
+        #[image:imgs/go.png|lightbox|alt:A go board]\n";
+
+    #[test]
+    fn formats_into_canonical_layout() {
+        assert_eq!(format_hmm(MESSY).unwrap(), "#[title:A place to dump my thoughts|timestamp:1605073292|tags:thinking]\n\n\
+            #[header|text:A Modest Header|date:Today]\n\n\
+            Some text about C# that wraps over lines.\n\n\
+            A #[url:test.com|text:test link] in the middle.\n\n\
+            #[image:imgs/go.png|alt:A go board|lightbox]\n");
+    }
+
+    #[test]
+    fn formatting_is_stable() {
+        let once = format_hmm(MESSY).unwrap();
+        assert_eq!(format_hmm(&once).unwrap(), once);
+    }
+
+    #[test]
+    fn lists_are_renumbered_and_reindented() {
+        let formatted = format_hmm("Some list:\n-   one\n    3. inner\n    7. more\n- two").unwrap();
+        assert_eq!(formatted, "Some list:\n\n- one\n  1. inner\n  2. more\n- two\n");
+    }
+
+    #[test]
+    fn quotes_keep_their_contents_between_tags() {
+        let formatted = format_hmm("#[quote|source:x.com|cite:Ada]\n\n\nOne\nline\n\nTwo\n#[/quote]").unwrap();
+        assert_eq!(formatted, "#[quote|cite:Ada|source:x.com]\nOne line\n\nTwo\n#[/quote]\n");
+    }
+
+    #[test]
+    fn tables_are_tidied() {
+        let formatted = format_hmm("#[table]\n|a|b|\n|:-|-:|\n|  1 |   |\n#[/table]").unwrap();
+        assert_eq!(formatted, "#[table]\n| a | b |\n| :-- | --: |\n| 1 | |\n#[/table]\n");
+    }
+
+    #[test]
+    fn galleries_list_one_image_per_line() {
+        let formatted = format_hmm("#[gallery]\n#[image:a.png|alt:A]  #[image:b.png]\n\n#[image:c.png]\n#[/gallery]").unwrap();
+        assert_eq!(formatted, "#[gallery]\n#[image:a.png|alt:A]\n#[image:b.png]\n#[image:c.png]\n#[/gallery]\n");
+        assert_eq!(format_hmm(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn crlf_files_stay_crlf() {
+        let formatted = format_hmm(&MESSY.replace('\n', "\r\n")).unwrap();
+        assert_eq!(formatted, format_hmm(MESSY).unwrap().replace('\n', "\r\n"));
+    }
+}
+
+// head key first, then the keys in the order the tag's schema documents them, then anything else
+fn format_tag(tag_type: &str, tag_string: &str) -> String {
+    let meta = content_compiler::parse_tag_meta(tag_string);
+
+    let schema_keys: Vec<&'static str> = tag_handlers::get_tag_handler(tag_type)
+        .and_then(|h| h.schema())
+        .map(|s| s.keys.iter().map(|k| k.name).collect())
+        .unwrap_or_default();
+
+    let mut keys: Vec<&String> = meta.keys().collect();
+    keys.sort_by_key(|k| {
+        let rank = if k.as_str() == tag_type {
+            0
+        } else {
+            schema_keys.iter().position(|s| s == k).map(|i| i + 1).unwrap_or(usize::MAX)
+        };
+        (rank, k.to_string())
+    });
+
+    let components: Vec<String> = keys.into_iter().map(|k| {
+        let value = meta.get(k).unwrap();
+        if value == "<empty>" {
+            k.clone()
+        } else {
+            format!("{}:{}", k, value)
+        }
+    }).collect();
+
+    return format!("#[{}]", components.join("|"));
+}
+
+// a paragraph's children all go on one line
+fn emit_inline(tokens: &Vec<Token>) -> String {
+    let mut line = String::new();
+    for token in tokens {
+        match token {
+            Span(text) => line.push_str(text),
+            Tag(tag_type, tag_string) => line.push_str(&format_tag(tag_type, tag_string)),
+            _ => {}
+        }
+    }
+    return line;
+}
+
+// one item per line, nested lists indented two spaces further than their parent item
+fn emit_list(ordered: bool, items: &Vec<Vec<Token>>, depth: usize, lines: &mut Vec<String>) {
+    for (i, item) in items.iter().enumerate() {
+        let marker = if ordered { format!("{}.", i + 1) } else { String::from("-") };
+        let (nested, inline): (Vec<&Token>, Vec<&Token>) = item.iter().partition(|t| matches!(t, List(..)));
+
+        let inline: Vec<Token> = inline.into_iter().cloned().collect();
+        lines.push(format!("{}{} {}", "  ".repeat(depth), marker, emit_inline(&inline)));
+        for token in nested {
+            if let List(ordered, items) = token {
+                emit_list(*ordered, items, depth + 1, lines);
+            }
+        }
+    }
+}
+
+// one row per line with the separator row under the header. cells aren't padded to line up, that
+// would churn every row whenever one cell changed
+fn emit_table(tag_string: &str, align: &Vec<String>, header_rows: usize, rows: &Vec<Vec<Vec<Token>>>) -> String {
+    let mut lines = vec![format_tag("table", tag_string)];
+    let row_line = |row: &Vec<Vec<Token>>| {
+        let cells: Vec<String> = row.iter().map(|cell| match emit_inline(cell).as_str() {
+            "" => String::from(" "),
+            text => format!(" {} ", text),
+        }).collect();
+        format!("|{}|", cells.join("|"))
+    };
+
+    for (i, row) in rows.iter().enumerate() {
+        if i == header_rows && !align.is_empty() {
+            lines.push(separator_line(align));
+        }
+        lines.push(row_line(row));
+    }
+    if rows.len() == header_rows && !align.is_empty() {
+        lines.push(separator_line(align));
+    }
+
+    lines.push(String::from("#[/table]"));
+    return lines.join("\n");
+}
+
+fn separator_line(align: &Vec<String>) -> String {
+    let cells: Vec<&str> = align.iter().map(|a| match a.as_str() {
+        "left" => " :-- ",
+        "center" => " :-: ",
+        "right" => " --: ",
+        _ => " --- ",
+    }).collect();
+    return format!("|{}|", cells.join("|"));
+}
+
+// a gallery's images go one per line rather than all on one, the spaces between them don't survive
+// compiling anyway. anything else in it is left as it is
+fn emit_gallery(tag_string: &str, children: &Vec<Token>) -> String {
+    let mut lines = vec![format_tag("gallery", tag_string)];
+    for child in children {
+        match child {
+            Paragraph(inline) if inline.iter().all(|t| matches!(t, Tag(..)) || matches!(t, Span(s) if s.trim().is_empty())) => {
+                for token in inline {
+                    if let Tag(tag_type, tag_string) = token {
+                        lines.push(format_tag(tag_type, tag_string));
+                    }
+                }
+            }
+            other => lines.push(emit_blocks(&vec![other.clone()])),
+        }
+    }
+    lines.push(String::from("#[/gallery]"));
+    return lines.join("\n");
+}
+
+fn emit_blocks(tokens: &Vec<Token>) -> String {
+    let mut blocks: Vec<String> = Vec::new();
+
+    for token in tokens {
+        match token {
+            Paragraph(children) => blocks.push(emit_inline(children)),
+            List(ordered, items) => {
+                let mut lines = Vec::new();
+                emit_list(*ordered, items, 0, &mut lines);
+                blocks.push(lines.join("\n"));
+            }
+            Tag(tag_type, tag_string) => blocks.push(format_tag(tag_type, tag_string)),
+            // kept as it was written, apart from the blank lines around it
+            Raw(text) => blocks.push(text.trim().replace('\r', "")),
+            Table(tag_string, align, header_rows, rows) => blocks.push(emit_table(tag_string, align, *header_rows, rows)),
+            Container(tag_type, tag_string, children) if &tag_type[..] == "gallery" => blocks.push(emit_gallery(tag_string, children)),
+            // the contents sit between the start and end tags, laid out like everything else
+            Container(tag_type, tag_string, children) => {
+                let start = format_tag(tag_type, tag_string);
+                let inner = emit_blocks(children);
+                if inner.is_empty() {
+                    blocks.push(format!("{}\n#[/{}]", start, tag_type));
+                } else {
+                    blocks.push(format!("{}\n{}\n#[/{}]", start, inner, tag_type));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    return blocks.join("\n\n");
+}
+
+fn emit(tokens: &Vec<Token>) -> String {
+    return emit_blocks(tokens) + "\n";
+}
+
+// re-emits a .hmm file in the canonical layout: one block per line, a blank line between blocks
+// and tag keys in a stable order. fails if the result wouldn't compile to the same page tokens
+pub fn format_hmm(contents: &str) -> Result<String, String> {
+    let tokens = content_compiler::run_passes(content_compiler::lex_content(contents.to_string()));
+    let mut formatted = emit(&tokens);
+
+    let no_file = String::from("");
+    let before = content_compiler::compile_hmm(&contents.to_string(), &no_file, &mut Vec::new());
+    let after = content_compiler::compile_hmm(&formatted, &no_file, &mut Vec::new());
+    if before.to_page_tokens() != after.to_page_tokens() {
+        return Err(String::from("formatting would change the compiled output"));
+    }
+
+    // keep whatever line endings the file already had
+    if contents.contains("\r\n") {
+        formatted = formatted.replace('\n', "\r\n");
+    }
+
+    return Ok(formatted);
+}
+
+pub fn find_hmm_files(dir: &Path) -> Vec<PathBuf> {
+    return WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .map(|e| e.into_path())
+        .filter(|e| e.is_file())
+        .filter(|e| e.extension().map(|ext| ext == "hmm").unwrap_or(false))
+        .collect::<Vec<PathBuf>>();
+}
+
+// formats every file in place, or with `check` only reports the ones which aren't formatted.
+// returns false if anything was unformatted (in check mode) or couldn't be formatted
+pub fn format_files(files: Vec<PathBuf>, check: bool) -> bool {
+    let mut ok = true;
+
+    for file in files {
+        let contents = match fs::read_to_string(&file) {
+            Ok(c) => c,
+            Err(e) => {
+                println!("{}: {}", file.display(), e);
+                ok = false;
+                continue;
+            }
+        };
+
+        let formatted = match format_hmm(&contents) {
+            Ok(f) => f,
+            Err(e) => {
+                println!("{}: {}", file.display(), e);
+                ok = false;
+                continue;
+            }
+        };
+
+        if formatted == contents { continue; }
+
+        if check {
+            println!("{}: not formatted", file.display());
+            ok = false;
+        } else {
+            println!("Formatting {}", file.display());
+            fs::write(&file, formatted).unwrap();
+        }
+    }
+
+    return ok;
+}
@@ -0,0 +1,156 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use git2::{Commit, DiffFormat, DiffOptions, Oid, Repository, Sort};
+use serde::Serialize;
+
+// reads post history straight out of the local repository the content lives in. git2 is built
+// without any of its network transports so this can never go off and talk to a remote.
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use git2::Signature;
+
+    fn commit_file(repo: &Repository, dir: &Path, name: &str, contents: &str, time: i64) -> Oid {
+        fs::write(dir.join(name), contents).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new(name)).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+
+        let signature = Signature::new("Ada", "ada@example.com", &git2::Time::new(time, 0)).unwrap();
+        let parent = repo.head().ok().and_then(|h| h.peel_to_commit().ok());
+        let parents: Vec<&Commit> = parent.iter().collect();
+        return repo.commit(Some("HEAD"), &signature, &signature, &format!("edit {}", name), &tree, &parents).unwrap();
+    }
+
+    #[test]
+    fn history_and_diffs_only_cover_the_file() {
+        let dir = std::env::temp_dir().join(format!("simple-blog-history-test-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        let repo = Repository::init(&dir).unwrap();
+
+        let first = commit_file(&repo, &dir, "post.hmm", "one\n", 1000);
+        let other = commit_file(&repo, &dir, "other.hmm", "unrelated\n", 2000);
+        let second = commit_file(&repo, &dir, "post.hmm", "one\ntwo\n", 3000);
+
+        let history = file_history(&dir.join("post.hmm")).unwrap();
+        let ids: Vec<&str> = history.revisions.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec![second.to_string(), first.to_string()]);
+        assert_eq!((history.created, history.updated), (1000, 3000));
+
+        let diff = file_diff(&dir.join("post.hmm"), &second.to_string()).unwrap();
+        assert!(diff.contains("+two"));
+
+        // anything that isn't one of the file's own revisions is refused, including revspecs
+        assert!(file_diff(&dir.join("post.hmm"), &other.to_string()).is_none());
+        assert!(file_diff(&dir.join("post.hmm"), "HEAD").is_none());
+        assert!(file_diff(&dir.join("post.hmm"), "HEAD~1").is_none());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Revision {
+    pub id: String,
+    pub summary: String,
+    pub author: String,
+    pub timestamp: u128,
+}
+
+#[derive(Debug, Clone)]
+pub struct FileHistory {
+    pub created: u128,
+    pub updated: u128,
+    // newest first
+    pub revisions: Vec<Revision>,
+}
+
+// finds the repo a file lives in and the file's path relative to the repo's working directory
+fn open_repo_for(file: &Path) -> Option<(Repository, PathBuf)> {
+    let abs_path = fs::canonicalize(file).ok()?;
+    let repo = Repository::discover(abs_path.parent()?).ok()?;
+    let work_dir = fs::canonicalize(repo.workdir()?).ok()?;
+    let rel_path = abs_path.strip_prefix(&work_dir).ok()?.to_path_buf();
+    return Some((repo, rel_path));
+}
+
+fn blob_id(commit: &Commit, rel_path: &Path) -> Option<Oid> {
+    let tree = commit.tree().ok()?;
+    let entry = tree.get_path(rel_path).ok()?;
+    return Some(entry.id());
+}
+
+pub fn file_history(file: &Path) -> Option<FileHistory> {
+    let (repo, rel_path) = open_repo_for(file)?;
+
+    let mut walk = repo.revwalk().ok()?;
+    walk.push_head().ok()?;
+    walk.set_sorting(Sort::TIME).ok()?;
+
+    let mut revisions = Vec::new();
+    for oid in walk.filter_map(|x| x.ok()) {
+        let commit = match repo.find_commit(oid) {
+            Ok(c) => c,
+            Err(_) => continue,
+        };
+
+        let current = blob_id(&commit, &rel_path);
+        if current.is_none() { continue; }
+
+        // only keep commits which actually touched this file
+        let previous = commit.parent(0).ok().and_then(|p| blob_id(&p, &rel_path));
+        if previous == current { continue; }
+
+        revisions.push(Revision {
+            id: commit.id().to_string(),
+            summary: commit.summary().unwrap_or("").to_string(),
+            author: commit.author().name().unwrap_or("").to_string(),
+            timestamp: commit.time().seconds() as u128,
+        });
+    }
+
+    if revisions.is_empty() {
+        return None;
+    }
+
+    return Some(FileHistory {
+        created: revisions.last().unwrap().timestamp,
+        updated: revisions.first().unwrap().timestamp,
+        revisions,
+    });
+}
+
+// renders the patch a single revision applied to the given file. `rev` has to be the full id of
+// one of the file's own revisions, so nothing else in the repo can be read through here
+pub fn file_diff(file: &Path, rev: &str) -> Option<String> {
+    let history = file_history(file)?;
+    if !history.revisions.iter().any(|r| r.id == rev) {
+        return None;
+    }
+
+    let (repo, rel_path) = open_repo_for(file)?;
+    let commit = repo.find_commit(Oid::from_str(rev).ok()?).ok()?;
+    let new_tree = commit.tree().ok()?;
+    let old_tree = commit.parent(0).ok().and_then(|p| p.tree().ok());
+
+    let mut opts = DiffOptions::new();
+    opts.pathspec(&rel_path);
+    let diff = repo.diff_tree_to_tree(old_tree.as_ref(), Some(&new_tree), Some(&mut opts)).ok()?;
+
+    let mut patch = String::new();
+    diff.print(DiffFormat::Patch, |_, _, line| {
+        match line.origin() {
+            '+' | '-' | ' ' => patch.push(line.origin()),
+            _ => {}
+        }
+        patch.push_str(&String::from_utf8_lossy(line.content()));
+        true
+    }).ok()?;
+
+    return Some(patch);
+}
@@ -0,0 +1,182 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use walkdir::WalkDir;
+
+use crate::content_compiler;
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::tokens::PageToken;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_problems_are_reported() {
+        let dir = std::env::temp_dir().join(format!("simple-blog-lint-test-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(dir.join("a")).unwrap();
+        fs::create_dir_all(dir.join("b")).unwrap();
+
+        fs::write(dir.join("a/post.hmm"), "#[title:One|slug:same]\n\nHi #[image:missing.png]").unwrap();
+        fs::write(dir.join("b/post.hmm"), "#[title:Two|slug:same]\n\nHi #[imgae:x.png]").unwrap();
+        fs::write(dir.join("b/untitled.hmm"), "No title here").unwrap();
+        fs::write(dir.join("b/unused.png"), "").unwrap();
+        fs::write(dir.join("b/table.hmm"), "#[title:Four]\n\n#[table:missing.csv]").unwrap();
+        fs::write(dir.join("b/unclosed.hmm"), "#[title:Three]\n\nHi #[image:x.png").unwrap();
+
+        let diagnostics = lint(&dir);
+        let messages: Vec<String> = diagnostics.iter().map(|d| d.message.clone()).collect();
+
+        assert!(messages.iter().any(|m| m.contains("missing.png") && m.contains("doesn't exist")));
+        assert!(messages.iter().any(|m| m.contains("table 'missing.csv' doesn't exist")));
+        assert!(messages.iter().any(|m| m.contains("unknown tag type 'imgae'")));
+        assert!(messages.iter().any(|m| m.contains("missing recommended key 'alt'")));
+        assert!(messages.iter().any(|m| m.contains("no title")));
+        assert!(messages.iter().any(|m| m.contains("slug 'same'")));
+        assert!(diagnostics.iter().any(|d| d.file.ends_with("unclosed.hmm") && d.line == 3 && d.message.contains("never closed")));
+        assert!(diagnostics.iter().any(|d| d.file.ends_with("unused.png")));
+        assert!(has_errors(&diagnostics));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
+
+pub fn has_errors(diagnostics: &Vec<Diagnostic>) -> bool {
+    return diagnostics.iter().any(|d| d.severity == Severity::Error);
+}
+
+fn check_title(tokens: &Vec<PageToken>, file_name: &String, diagnostics: &mut Vec<Diagnostic>) {
+    let has_title = tokens.iter()
+        .filter(|t| t.token_type == "title")
+        .filter_map(|t| t.meta.get("title"))
+        .any(|t| !t.is_empty() && t != "<empty>");
+
+    if !has_title {
+        diagnostics.push(Diagnostic::for_file(Severity::Error, file_name, String::from("post has no title")));
+    }
+}
+
+fn check_paragraphs(tokens: &Vec<PageToken>, file_name: &String, diagnostics: &mut Vec<Diagnostic>) {
+    let mut paragraph: Option<Vec<&PageToken>> = None;
+    for token in tokens {
+        match token.token_type.as_str() {
+            "para_start" => paragraph = Some(Vec::new()),
+            "para_end" => {
+                let children = paragraph.take().unwrap_or_default();
+                let is_empty = children.iter().all(|t| {
+                    t.token_type == "span" && t.meta.get("text").map(|x| x.trim().is_empty()).unwrap_or(true)
+                });
+                if is_empty {
+                    diagnostics.push(Diagnostic::for_file(Severity::Warning, file_name, String::from("empty paragraph")));
+                }
+            }
+            _ => {
+                if let Some(children) = paragraph.as_mut() {
+                    children.push(token);
+                }
+            }
+        }
+    }
+}
+
+// the tag and key of everything that points at a file next to the post
+const FILE_KEYS: &[(&str, &str)] = &[
+    ("image", "image"),
+    ("video", "video"),
+    ("video", "poster"),
+    ("audio", "audio"),
+    ("table", "table"),
+];
+
+// relative files have to exist next to the post, returns the ones that do so we know which
+// assets are in use
+fn check_files(tokens: &Vec<PageToken>, post_dir: &Path, file_name: &String, diagnostics: &mut Vec<Diagnostic>) -> Vec<PathBuf> {
+    let mut used = Vec::new();
+    for token in tokens {
+        let keys = FILE_KEYS.iter().filter(|(tag_type, _)| *tag_type == token.token_type);
+        for (tag_type, key) in keys {
+            let file = match token.meta.get(*key) {
+                Some(f) if !f.contains("://") && f != "<empty>" => f,
+                _ => continue,
+            };
+
+            match fs::canonicalize(post_dir.join(file)) {
+                Ok(path) => used.push(path),
+                Err(_) => {
+                    diagnostics.push(Diagnostic::for_file(
+                        Severity::Error,
+                        file_name,
+                        format!("{} '{}' doesn't exist", tag_type, file),
+                    ));
+                }
+            }
+        }
+    }
+    return used;
+}
+
+// compiles everything under content_dir without touching the site cache and reports anything
+// that looks broken
+pub fn lint(content_dir: &Path) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    let mut used_assets: HashSet<PathBuf> = HashSet::new();
+    let mut slugs: HashMap<String, String> = HashMap::new();
+
+    let files = WalkDir::new(content_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .map(|e| e.into_path())
+        .filter(|e| e.is_file())
+        .collect::<Vec<PathBuf>>();
+
+    for file in files.iter().filter(|f| content_compiler::is_content_file(f)) {
+        let file_name: String = file.to_str().unwrap().into();
+        let contents = match fs::read_to_string(file) {
+            Ok(c) => c,
+            Err(e) => {
+                diagnostics.push(Diagnostic::for_file(Severity::Error, &file_name, e.to_string()));
+                continue;
+            }
+        };
+
+        let document = match content_compiler::compile_document(file, &contents, &file_name, None, &mut diagnostics) {
+            Some(document) => document,
+            None => continue,
+        };
+        let tokens = document.to_page_tokens();
+
+        check_title(&tokens, &file_name, &mut diagnostics);
+        check_paragraphs(&tokens, &file_name, &mut diagnostics);
+        used_assets.extend(check_files(&tokens, file.parent().unwrap(), &file_name, &mut diagnostics));
+
+        let slug = content_compiler::decide_slug(&document, file);
+        if let Some(other) = slugs.get(&slug) {
+            diagnostics.push(Diagnostic::for_file(
+                Severity::Error,
+                &file_name,
+                format!("slug '{}' is already used by {}", slug, other),
+            ));
+        } else {
+            slugs.insert(slug, file_name.clone());
+        }
+    }
+
+    // anything that isn't a post and isn't used by one is just taking up space
+    for file in files.iter().filter(|f| !content_compiler::is_content_file(f)) {
+        let is_hidden = file.file_name().and_then(|n| n.to_str()).map(|n| n.starts_with('.')).unwrap_or(false);
+        if is_hidden { continue; }
+
+        let path = fs::canonicalize(file).unwrap_or(file.clone());
+        if !used_assets.contains(&path) {
+            diagnostics.push(Diagnostic::for_file(
+                Severity::Warning,
+                &file.to_str().unwrap().to_string(),
+                String::from("asset isn't used by any post"),
+            ));
+        }
+    }
+
+    return diagnostics;
+}
@@ -3,91 +3,208 @@
 #[macro_use]
 extern crate rocket;
 
-use std::collections::HashMap;
 use std::fs::{self, DirEntry};
 use std::{io, thread};
 use std::path::{Path, PathBuf};
 
 use chrono::{DateTime, Utc};
-use itertools::Itertools;
 use rocket_contrib::templates::Template;
 use serde::{Deserialize, Serialize};
 
-use crate::tokens::PageToken;
+use crate::cli::Command;
+use crate::config::SiteConfig;
+use crate::content_compiler::CompileOptions;
+use crate::ranged_file::{RangeHeader, RangedFile};
+use rocket::http::ContentType;
 use rocket::response::NamedFile;
+use rocket::response::content::Content;
 use rocket::Config;
 use rocket::config::Environment;
 
+mod api;
+mod ast;
+mod cli;
+mod config;
 mod content_compiler;
 mod tokens;
 mod site_cache;
 mod content_monitor;
+mod diagnostics;
+mod feed;
+mod formatter;
+mod git_history;
+mod linter;
+mod markdown_export;
+mod markdown_import;
+mod math;
+mod media;
+mod pages;
+mod ranged_file;
+mod render;
+mod scaffold;
+mod search;
+mod site_builder;
+mod tables;
+mod tag_handlers;
 
 fn main() {
-    content_compiler::load_site_content();
+    let (site_config, command) = match cli::parse_args() {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    config::set_config(site_config.clone());
+    let content_dir = PathBuf::from(&site_config.content_dir);
+
+    match command {
+        Command::Serve => {
+            load_or_exit(&content_dir, &site_config);
+
+            // watch the content/ path for any changes
+            let options = CompileOptions { strict: site_config.strict };
+            let watched_dir = content_dir.clone();
+            let handle = thread::spawn(move || {
+                content_monitor::start_monitor(watched_dir, options);
+            });
+
+            rocket(&site_config).launch();
+        }
+        Command::Build => {
+            load_or_exit(&content_dir, &site_config);
+            if let Err(e) = site_builder::build_site(&rocket(&site_config), &site_config) {
+                println!("Build failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::Lint => {
+            let diagnostics = linter::lint(&content_dir);
+            for d in diagnostics.iter() {
+                println!("{}", d);
+            }
+            if linter::has_errors(&diagnostics) {
+                std::process::exit(1);
+            }
+        }
+        Command::New { title, tags } => {
+            match scaffold::new_post(&content_dir, &title, &tags, chrono::Local::now()) {
+                Ok(file) => println!("Created {}", file.display()),
+                Err(e) => {
+                    println!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::Fmt { check, files } => {
+            let mut files: Vec<PathBuf> = files.iter().map(PathBuf::from).collect();
+            if files.is_empty() {
+                files = formatter::find_hmm_files(&content_dir);
+            }
+            if !formatter::format_files(files, check) {
+                std::process::exit(1);
+            }
+        }
+        Command::ExportMarkdown { out_dir } => {
+            let problems = markdown_export::export_markdown(&content_dir, Path::new(&out_dir));
+            for problem in problems.iter() {
+                println!("{}", problem);
+            }
+        }
+    }
+}
 
-    // watch the content/ path for any changes
-    let handle = thread::spawn(|| {
-        content_monitor::start_monitor();
-    });
+fn load_or_exit(content_dir: &Path, site_config: &SiteConfig) {
+    let options = CompileOptions { strict: site_config.strict };
+    if content_compiler::load_site_content(content_dir, &options).is_err() {
+        std::process::exit(1);
+    }
+}
 
+fn rocket(site_config: &SiteConfig) -> rocket::Rocket {
     let config = Config::build(Environment::Staging)
-        .address("0.0.0.0")
-        .port(8000)
+        .address(site_config.address.clone())
+        .port(site_config.port)
+        .extra("template_dir", site_config.templates_dir.clone())
         .finalize().unwrap();
 
-    //rocket::ignite()
     rocket::custom(config)
         .attach(Template::fairing())
-        .mount("/", routes![index, archive_list, site_content, get_static])
-        .launch();
+        .mount("/", routes![index, index_page, feed_xml, search_page, search_index, post, archive_list, site_content, get_static, history_list, history_diff])
+        .mount("/api", api::routes())
 }
 
 #[get("/site-content/<page_hash>/<file..>")]
-fn site_content(page_hash: String, file: PathBuf) -> Option<NamedFile> {
+fn site_content(page_hash: String, file: PathBuf, range: RangeHeader) -> Option<RangedFile> {
     if let Some(root) = site_cache::get_page_root(&page_hash) {
         let root_path = Path::new(&root);
-        return NamedFile::open(root_path.join(file)).ok()
+        return RangedFile::open(root_path.join(file), range)
     }
     return None
 }
 
+#[get("/history/<page_hash>")]
+fn history_list(page_hash: String) -> Option<String> {
+    let page = site_cache::get_page_by_hash(&page_hash)?;
+    let lines: Vec<String> = page.revisions.iter()
+        .map(|r| format!("{} {} {} ({})", r.id, r.timestamp, r.summary, r.author))
+        .collect();
+    return Some(lines.join("\n"));
+}
+
+#[get("/history/<page_hash>/<rev>")]
+fn history_diff(page_hash: String, rev: String) -> Option<String> {
+    let page = site_cache::get_page_by_hash(&page_hash)?;
+    let rev = rev.trim_end_matches(".diff");
+    return git_history::file_diff(Path::new(&page.file_name), rev);
+}
+
 #[get("/static/<file..>")]
 fn get_static(file: PathBuf) -> Option<NamedFile> {
-    NamedFile::open(Path::new("static/").join(file)).ok()
+    let static_dir = config::get_config().static_dir;
+    NamedFile::open(Path::new(&static_dir).join(file)).ok()
 }
 
-fn footer() -> Vec<PageToken> {
-    let mut meta = HashMap::new();
-
-    return vec![PageToken {
-        token_type: String::from("footer"),
-        meta,
-    }];
+#[get("/")]
+fn index() -> Option<Template> {
+    Some(Template::render("index", pages::index_page(1)?))
 }
 
-#[derive(Serialize, Debug)]
-struct RenderedPage {
-    title: String,
-    body: Vec<PageToken>,
+#[get("/page/<page>")]
+fn index_page(page: usize) -> Option<Template> {
+    Some(Template::render("index", pages::index_page(page)?))
 }
 
-#[get("/")]
-fn index() -> Template {
-    let mut pages = site_cache::get_all_site_content();
-    pages.sort();
+#[get("/feed.xml")]
+fn feed_xml() -> Option<Content<String>> {
+    let site = config::get_config().site;
+    if !site.feed.enabled {
+        return None;
+    }
+    return Some(Content(ContentType::new("application", "atom+xml"), feed::atom_feed(&site)));
+}
 
-    let tokens: Vec<PageToken> = pages.into_iter().map(|p| { p.page_tokens })
-        .intersperse(footer())
-        .flat_map(|x| x)
-        .collect();
+#[get("/search?<q>")]
+fn search_page(q: Option<String>) -> Option<Template> {
+    if !config::get_config().site.features.search {
+        return None;
+    }
+    return Some(Template::render("search", pages::search_page(q.unwrap_or_default())));
+}
 
-    let context = RenderedPage {
-        title: String::from("Max's Thoughts & Feelings"),
-        body: tokens,
-    };
+#[get("/search-index.json")]
+fn search_index() -> Option<Content<String>> {
+    if !config::get_config().site.features.search {
+        return None;
+    }
+    return Some(Content(ContentType::JSON, search::client_index_json()));
+}
 
-    Template::render("index", &context)
+#[get("/post/<page_hash>")]
+fn post(page_hash: String) -> Option<Template> {
+    let page = site_cache::get_page_by_hash(&page_hash)?;
+    let (template, context) = pages::post_page(page);
+    Some(Template::render(template, context))
 }
 
 #[get("/archive")]
@@ -0,0 +1,388 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use walkdir::WalkDir;
+
+use crate::content_compiler;
+use crate::tag_handlers;
+use crate::tokens::PageToken;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markdown_import;
+
+    #[test]
+    fn hmm_exports_to_markdown() {
+        let tokens = content_compiler::compile_hmm(
+            &"#[title: A *post*|timestamp:1605073292|tags:thinking]\n\n\
+            #[header|text:A Header|date:Today]\n\n\
+            A #[url:test.com|text:test link] in a span.\n\n\
+            #[image:./imgs/go.png|lightbox]\n\n\
+            #[github_gist|url:gist.github.com/x.js]\n\n\
+            - one\n  1. inner\n- two\n\n\
+            #[quote|cite:Ada|source:x.com]\nOne\n\nTwo\n#[/quote]".to_string(),
+            &"test.hmm".to_string(),
+            &mut Vec::new(),
+        ).to_page_tokens();
+
+        let mut problems = Vec::new();
+        let markdown = to_markdown(&tokens, &"test.hmm".to_string(), &mut problems);
+
+        assert_eq!(markdown, "---\ntitle: \"A *post*\"\ntimestamp: 1605073292\ntags: [thinking]\n---\n\n\
+            ## A Header\n\n_Today_\n\n\
+            A [test link](https://test.com) in a span.\n\n\
+            ![](imgs/go.png)\n\n\
+            [gist](https://gist.github.com/x.js)\n\n\
+            - one\n   1. inner\n- two\n\n\
+            > One\n>\n> Two\n>\n> — [Ada](https://x.com)\n\n");
+        assert_eq!(problems.len(), 1);
+    }
+
+    #[test]
+    fn math_and_footnotes_are_reported() {
+        let token = |token_type: &str, meta: Vec<(&str, &str)>| PageToken {
+            token_type: token_type.to_string(),
+            meta: meta.into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        };
+        let tokens = vec![
+            token("para_start", vec![]),
+            token("math", vec![("math", "\\pi r^2")]),
+            token("fn", vec![("fn", "roughly"), ("number", "1"), ("anchor", "h-1")]),
+            token("para_end", vec![]),
+            token("math", vec![("math", "x"), ("display", "<empty>")]),
+            token("footnotes_start", vec![]),
+            token("footnote", vec![("text", "roughly"), ("number", "1"), ("anchor", "h-1")]),
+            token("footnotes_end", vec![]),
+        ];
+
+        let mut problems = Vec::new();
+        let markdown = to_markdown(&tokens, &"test.hmm".to_string(), &mut problems);
+        assert_eq!(markdown, "$\\pi r^2$[^1]\n\n$$\nx\n$$\n\n[^1]: roughly\n\n");
+        assert_eq!(problems.iter().filter(|p| p.contains("'math'")).count(), 2);
+        assert_eq!(problems.iter().filter(|p| p.contains("'fn'")).count(), 1);
+    }
+
+    #[test]
+    fn exports_import_back_the_same() {
+        let token = |token_type: &str, meta: Vec<(&str, &str)>| PageToken {
+            token_type: token_type.to_string(),
+            meta: meta.into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        };
+        let tokens = vec![
+            token("title", vec![("title", "A \"quoted\" C:\\ post"), ("timestamp", "1605073292")]),
+            token("para_start", vec![]),
+            token("span", vec![("text", "#1 is <b>not</b> *bold* [really]")]),
+            token("para_end", vec![]),
+        ];
+
+        let markdown = to_markdown(&tokens, &"test.hmm".to_string(), &mut Vec::new());
+        let mut diagnostics = Vec::new();
+        let imported = markdown_import::markdown_to_page_tokens(&markdown, &"test.md".to_string(), &mut diagnostics).unwrap();
+
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        assert_eq!(imported[0].meta.get("title").unwrap(), "A \"quoted\" C:\\ post");
+        assert_eq!(imported[2].meta.get("text").unwrap(), "#1 is <b>not</b> *bold* [really]");
+    }
+}
+
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::new();
+    let mut line_start = true;
+    for ch in text.chars() {
+        // `<` could start inline html, and a `#` starting a line would make it a header
+        if "\\`*_[]<".contains(ch) || (ch == '#' && line_start) {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+        line_start = ch == '\n' || (line_start && ch.is_whitespace());
+    }
+    return escaped;
+}
+
+// "./imgs/go.png" -> "imgs/go.png"
+fn relative_link(path: &String) -> String {
+    return path.trim_start_matches("./").to_string();
+}
+
+fn front_matter(title: &PageToken) -> String {
+    let mut lines = vec![String::from("---")];
+
+    let mut keys: Vec<&String> = title.meta.keys().collect();
+    keys.sort_by_key(|k| match k.as_str() {
+        "title" => 0,
+        "timestamp" => 1,
+        "tags" => 2,
+        _ => 3,
+    });
+
+    for key in keys {
+        let value = title.meta.get(key).unwrap();
+        let line = match key.as_str() {
+            "title" => format!("title: \"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")),
+            "tags" => {
+                let tags: Vec<&str> = value.split(',').map(|t| t.trim()).collect();
+                format!("tags: [{}]", tags.join(", "))
+            }
+            _ => format!("{}: {}", key, value),
+        };
+        lines.push(line);
+    }
+
+    lines.push(String::from("---"));
+    return lines.join("\n") + "\n\n";
+}
+
+// where we are in a table, gfm tables have exactly one header row followed by the separator
+struct TableState {
+    align: Vec<String>,
+    separator_written: bool,
+    header_row: bool,
+    cells: usize,
+}
+
+fn separator_row(align: &Vec<String>, cells: usize) -> String {
+    let cells: Vec<&str> = (0..cells).map(|i| match align.get(i).map(|a| a.as_str()) {
+        Some("left") => ":--",
+        Some("center") => ":-:",
+        Some("right") => "--:",
+        _ => "---",
+    }).collect();
+    return format!("| {} |\n", cells.join(" | "));
+}
+
+// renders compiled page tokens as markdown. tokens which have no markdown equivalent are noted in
+// `problems` and kept as close as we can get
+fn to_markdown(tokens: &Vec<PageToken>, file_name: &String, problems: &mut Vec<String>) -> String {
+    let mut markdown = String::new();
+    // for every list we're in: whether it's ordered and how many items it's had so far
+    let mut lists: Vec<(bool, usize)> = Vec::new();
+    // for every quote we're in: where its contents start and its attribution line
+    let mut quotes: Vec<(usize, String)> = Vec::new();
+    let mut table: Option<TableState> = None;
+    // how many galleries we're in
+    let mut galleries: usize = 0;
+
+    for token in tokens {
+        let meta = |key: &str| token.meta.get(key).cloned().unwrap_or_default();
+        match token.token_type.as_str() {
+            "title" => markdown.insert_str(0, &front_matter(token)),
+            "header" => {
+                markdown.push_str(&format!("## {}\n\n", escape_markdown(&meta("text"))));
+                if token.meta.contains_key("date") {
+                    markdown.push_str(&format!("_{}_\n\n", escape_markdown(&meta("date"))));
+                }
+            }
+            "para_start" => {}
+            "para_end" => markdown.push_str("\n\n"),
+            "span" if table.is_some() => markdown.push_str(&escape_markdown(&meta("text")).replace('|', "\\|")),
+            "span" => markdown.push_str(&escape_markdown(&meta("text"))),
+            "url" => {
+                let href = tag_handlers::link_href(&meta("url"));
+                markdown.push_str(&format!("[{}]({})", escape_markdown(&meta("text")), href));
+            }
+            "image" => {
+                let alt = escape_markdown(&meta("alt"));
+                match token.meta.get("caption") {
+                    Some(caption) => markdown.push_str(&format!("![{}]({} \"{}\")", alt, relative_link(&meta("image")), caption.replace('"', "\\\""))),
+                    None => markdown.push_str(&format!("![{}]({})", alt, relative_link(&meta("image")))),
+                }
+                // a gallery's images are one after the other, markdown has no grid to put them in
+                if galleries > 0 { markdown.push('\n'); }
+            }
+            "gallery_start" => galleries += 1,
+            "gallery_end" => {
+                galleries = galleries.saturating_sub(1);
+                markdown.push('\n');
+            }
+            "em_start" | "em_end" => markdown.push('_'),
+            "strong_start" | "strong_end" => markdown.push_str("**"),
+            "code" => markdown.push_str(&format!("`{}`", meta("text"))),
+            "code_block" => markdown.push_str(&format!("```\n{}```\n\n", meta("text"))),
+            "line_break" => markdown.push_str("  \n"),
+            "list_start" => {
+                // a nested list starts on the line after its parent item's text
+                if !lists.is_empty() { markdown.push('\n'); }
+                lists.push((token.meta.contains_key("ordered"), 0));
+            }
+            "item_start" => {
+                let depth = lists.len().saturating_sub(1);
+                if let Some((ordered, count)) = lists.last_mut() {
+                    *count += 1;
+                    let marker = if *ordered { format!("{}.", count) } else { String::from("-") };
+                    markdown.push_str(&format!("{}{} ", "   ".repeat(depth), marker));
+                }
+            }
+            "item_end" => {
+                if !markdown.ends_with('\n') { markdown.push('\n'); }
+            }
+            "list_end" => {
+                lists.pop();
+                if lists.is_empty() { markdown.push('\n'); }
+            }
+            "quote_start" => {
+                let cite = token.meta.get("cite").filter(|c| *c != "<empty>").map(|c| escape_markdown(c));
+                let href = token.meta.get("source").filter(|s| *s != "<empty>").map(|s| tag_handlers::link_href(s));
+                let attribution = match (cite, href) {
+                    (Some(cite), Some(href)) => format!("— [{}]({})", cite, href),
+                    (Some(cite), None) => format!("— {}", cite),
+                    (None, Some(href)) => format!("— <{}>", href),
+                    (None, None) => String::new(),
+                };
+                quotes.push((markdown.len(), attribution));
+            }
+            "quote_end" => {
+                // everything since the start gets quoted, nested quotes end up with `> > `
+                if let Some((start, attribution)) = quotes.pop() {
+                    let mut inner = markdown.split_off(start).trim_end().to_string();
+                    if !attribution.is_empty() {
+                        inner.push_str(&format!("\n\n{}", attribution));
+                    }
+                    let quoted: Vec<String> = inner.lines()
+                        .map(|l| if l.is_empty() { String::from(">") } else { format!("> {}", l) })
+                        .collect();
+                    markdown.push_str(&(quoted.join("\n") + "\n\n"));
+                }
+            }
+            // `$` math and `[^n]` footnotes are extensions, plain Markdown and our own importer show them as text
+            "math" => {
+                problems.push(format!("{}: 'math' has no Markdown equivalent, exported as $TeX$", file_name));
+                if token.meta.contains_key("display") {
+                    markdown.push_str(&format!("$$\n{}\n$$\n\n", meta("math")));
+                } else {
+                    markdown.push_str(&format!("${}$", meta("math")));
+                }
+            }
+            "fn" => {
+                problems.push(format!("{}: 'fn' has no Markdown equivalent, exported as a [^n] footnote", file_name));
+                markdown.push_str(&format!("[^{}]", meta("number")));
+            }
+            "footnotes_start" | "footnotes_end" => {}
+            "footnote" => markdown.push_str(&format!("[^{}]: {}\n\n", meta("number"), escape_markdown(&meta("text")))),
+            "table_start" => {
+                let align = meta("align").split(',').map(String::from).collect();
+                table = Some(TableState { align, separator_written: false, header_row: false, cells: 0 });
+            }
+            "row_start" => {
+                if let Some(state) = table.as_mut() {
+                    state.header_row = token.meta.contains_key("header");
+                    state.cells = 0;
+                }
+                markdown.push('|');
+            }
+            "cell_start" => markdown.push(' '),
+            "cell_end" => {
+                if let Some(state) = table.as_mut() { state.cells += 1; }
+                markdown.push_str(" |");
+            }
+            "row_end" => {
+                markdown.push('\n');
+                if let Some(state) = table.as_mut() {
+                    if !state.separator_written {
+                        if !state.header_row {
+                            problems.push(format!("{}: Markdown tables need a header row, the first row was used", file_name));
+                        }
+                        markdown.push_str(&separator_row(&state.align, state.cells));
+                        state.separator_written = true;
+                    }
+                }
+            }
+            "table_end" => {
+                table = None;
+                markdown.push('\n');
+            }
+            "rule" => markdown.push_str("---\n\n"),
+            "video" => {
+                problems.push(format!("{}: 'video' has no Markdown equivalent, exported as a link", file_name));
+                let video = relative_link(&meta("video"));
+                match token.meta.get("poster") {
+                    Some(poster) => markdown.push_str(&format!("[![]({})]({})\n\n", relative_link(poster), video)),
+                    None => markdown.push_str(&format!("[video]({})\n\n", video)),
+                }
+            }
+            "audio" => {
+                problems.push(format!("{}: 'audio' has no Markdown equivalent, exported as a link", file_name));
+                markdown.push_str(&format!("[audio]({})\n\n", relative_link(&meta("audio"))));
+            }
+            "github_gist" => {
+                problems.push(format!("{}: 'github_gist' has no Markdown equivalent, exported as a plain link", file_name));
+                markdown.push_str(&format!("[gist]({})\n\n", tag_handlers::link_href(&meta("url"))));
+            }
+            other => {
+                problems.push(format!("{}: '{}' has no Markdown equivalent, dropped", file_name, other));
+            }
+        }
+    }
+
+    return markdown;
+}
+
+// copies every relative image, video and audio file a post uses next to its exported markdown
+fn copy_files(tokens: &Vec<PageToken>, source_dir: &Path, target_dir: &Path, problems: &mut Vec<String>) {
+    let files = tokens.iter().flat_map(|t| match t.token_type.as_str() {
+        "image" => vec![t.meta.get("image")],
+        "video" => vec![t.meta.get("video"), t.meta.get("poster")],
+        "audio" => vec![t.meta.get("audio")],
+        _ => vec![],
+    });
+    for file in files {
+        let file = match file {
+            Some(f) if !f.contains("://") => relative_link(f),
+            _ => continue,
+        };
+
+        let target = target_dir.join(&file);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).ok();
+        }
+        if let Err(e) = fs::copy(source_dir.join(&file), &target) {
+            problems.push(format!("couldn't copy {}: {}", source_dir.join(&file).display(), e));
+        }
+    }
+}
+
+// walks content_dir and writes a markdown twin of every .hmm post into out_dir, keeping the
+// directory layout. returns everything that couldn't be exported faithfully
+pub fn export_markdown(content_dir: &Path, out_dir: &Path) -> Vec<String> {
+    let mut problems: Vec<String> = Vec::new();
+
+    let entries = WalkDir::new(content_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .map(|e| e.into_path())
+        .filter(|e| e.is_file())
+        .filter(|e| e.extension().map(|ext| ext == "hmm").unwrap_or(false))
+        .collect::<Vec<PathBuf>>();
+
+    for file in entries {
+        let file_name: String = file.to_str().unwrap().into();
+        let contents = match fs::read_to_string(&file) {
+            Ok(c) => c,
+            Err(e) => {
+                problems.push(format!("{}: {}", file_name, e));
+                continue;
+            }
+        };
+
+        let mut diagnostics = Vec::new();
+        let tokens = content_compiler::compile_hmm(&contents, &file_name, &mut diagnostics).to_page_tokens();
+        for d in diagnostics {
+            problems.push(d.to_string());
+        }
+
+        let markdown = to_markdown(&tokens, &file_name, &mut problems);
+
+        let relative = file.strip_prefix(content_dir).unwrap();
+        let target = out_dir.join(relative).with_extension("md");
+        let target_dir = target.parent().unwrap();
+        fs::create_dir_all(target_dir).unwrap();
+        copy_files(&tokens, file.parent().unwrap(), target_dir, &mut problems);
+
+        println!("Exporting {} -> {}", file_name, target.display());
+        if let Err(e) = fs::write(&target, markdown) {
+            problems.push(format!("couldn't write {}: {}", target.display(), e));
+        }
+    }
+
+    return problems;
+}
@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+
+use pulldown_cmark::{Event, Options, Parser, Tag};
+
+use crate::diagnostics::{self, Diagnostic, Severity};
+use crate::tag_handlers;
+use crate::tokens::PageToken;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_without_front_matter_are_skipped() {
+        assert!(markdown_to_page_tokens("# Just a readme\n\nNothing to see here.", &"readme.md".to_string(), &mut Vec::new()).is_none());
+    }
+
+    #[test]
+    fn markdown_becomes_page_tokens() {
+        let tokens = markdown_to_page_tokens(
+            "---\ntitle: Hello\ntimestamp: 1605073296\ntags: [rust, thinking]\n---\n\n\
+            # A Header\n\nSome *emphasis* and a [link](https://test.com) with `code`.\n\n![a go board](imgs/go.png)\n",
+            &"post.md".to_string(),
+            &mut Vec::new(),
+        ).unwrap();
+
+        let types: Vec<&str> = tokens.iter().map(|t| t.token_type.as_str()).collect();
+        assert_eq!(types, vec![
+            "title", "header",
+            "para_start", "span", "em_start", "span", "em_end", "span", "url", "span", "code", "span", "para_end",
+            "para_start", "image", "para_end",
+        ]);
+
+        assert_eq!(tokens[0].meta.get("title").unwrap(), "Hello");
+        assert_eq!(tokens[0].meta.get("tags").unwrap(), "rust,thinking");
+        assert_eq!(tokens[1].meta.get("text").unwrap(), "A Header");
+        assert_eq!(tokens[8].meta.get("url").unwrap(), "https://test.com");
+        assert_eq!(tokens[8].meta.get("text").unwrap(), "link");
+        assert_eq!(tokens[14].meta.get("image").unwrap(), "imgs/go.png");
+        assert_eq!(tokens[14].meta.get("alt").unwrap(), "a go board");
+    }
+
+    #[test]
+    fn lists_become_list_tokens() {
+        let tokens = markdown_to_page_tokens("---\ntitle: Lists\n---\n\n- one\n  1. inner\n- two\n", &"lists.md".to_string(), &mut Vec::new()).unwrap();
+
+        let types: Vec<&str> = tokens.iter().map(|t| t.token_type.as_str()).collect();
+        assert_eq!(types, vec![
+            "title",
+            "list_start", "item_start", "span",
+            "list_start", "item_start", "span", "item_end", "list_end",
+            "item_end", "item_start", "span", "item_end", "list_end",
+        ]);
+        assert!(tokens[4].meta.contains_key("ordered"));
+    }
+
+    #[test]
+    fn problems_are_reported() {
+        let mut diagnostics = Vec::new();
+        let tokens = markdown_to_page_tokens(
+            "---\ntitle: Problems\ntimestamp: yesterday\n---\n\nA [link with *emphasis*](x.com).\n\n<div>raw</div>\n",
+            &"post.md".to_string(),
+            &mut diagnostics,
+        ).unwrap();
+
+        let messages: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+        assert_eq!(messages, vec![
+            "post.md:3:1: error: 'title' key 'timestamp' expects a number, got 'yesterday'",
+            "post.md:8:1: warning: raw HTML isn't supported, it was left out",
+        ]);
+
+        // the emphasis stays inside the link's text rather than splitting it
+        let types: Vec<&str> = tokens.iter().map(|t| t.token_type.as_str()).collect();
+        assert_eq!(types, vec!["title", "para_start", "span", "url", "span", "para_end"]);
+        assert_eq!(tokens[3].meta.get("text").unwrap(), "link with emphasis");
+    }
+}
+
+fn page_token(token_type: &str, meta: Vec<(&str, String)>) -> PageToken {
+    PageToken {
+        token_type: token_type.to_string(),
+        meta: meta.into_iter().map(|(k, v)| (k.to_string(), v)).collect(),
+    }
+}
+
+// `key: value` lines between two `---` lines at the very top of the file, along with the line
+// each key is on
+struct FrontMatter {
+    meta: HashMap<String, String>,
+    lines: HashMap<String, usize>,
+}
+
+// a quoted value can have `\"` and `\\` in it, the way the markdown export writes titles
+fn unescape_quoted(value: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = value.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => unescaped.extend(chars.next()),
+            _ => unescaped.push(ch),
+        }
+    }
+    return unescaped;
+}
+
+fn split_front_matter(contents: &str) -> Option<(FrontMatter, &str)> {
+    let first_line_end = contents.find('\n')?;
+    if contents[..first_line_end].trim() != "---" {
+        return None;
+    }
+
+    let mut meta = HashMap::new();
+    let mut lines = HashMap::new();
+    let mut line_number = 1;
+    let mut offset = first_line_end + 1;
+    while offset < contents.len() {
+        line_number += 1;
+        let line_end = contents[offset..].find('\n')
+            .map(|i| offset + i + 1)
+            .unwrap_or(contents.len());
+        let line = contents[offset..line_end].trim();
+        offset = line_end;
+
+        if line == "---" {
+            return Some((FrontMatter { meta, lines }, &contents[offset..]));
+        }
+
+        let mut components = line.splitn(2, ':');
+        let key = components.next().unwrap().trim();
+        let value = components.next().unwrap_or("").trim();
+        if key.is_empty() { continue; }
+
+        // quotes are optional, and `tags: [a, b]` is stored the same way a title tag would, `tags:a,b`
+        let value = if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+            unescape_quoted(&value[1..value.len() - 1])
+        } else if value.starts_with('[') && value.ends_with(']') {
+            value[1..value.len() - 1].split(',').map(|v| v.trim()).collect::<Vec<_>>().join(",")
+        } else {
+            value.to_string()
+        };
+        meta.insert(key.to_string(), value);
+        lines.insert(key.to_string(), line_number);
+    }
+
+    // never found the closing ---
+    return None;
+}
+
+fn finish_span(span: &mut String, tokens: &mut Vec<PageToken>) {
+    if span.trim().len() > 0 {
+        tokens.push(page_token("span", vec![("text", span.clone())]));
+    }
+    span.clear();
+}
+
+// the front matter is checked like a title tag would be. problems point at the key they're about,
+// or the top of the file when it's missing
+fn check_front_matter(front_matter: &FrontMatter, file_name: &String) -> Vec<Diagnostic> {
+    return tag_handlers::check_tag("title", &front_matter.meta).into_iter().map(|(severity, message)| {
+        let line = front_matter.lines.iter()
+            .filter(|(key, _)| message.contains(&format!("key '{}'", key)))
+            .map(|(_, line)| *line)
+            .min()
+            .unwrap_or(1);
+        Diagnostic { severity, file: file_name.clone(), line, column: 1, message }
+    }).collect();
+}
+
+// turns a markdown post into the same token stream a .hmm post compiles to, the front matter
+// becomes the title tag. returns None for markdown files which aren't posts
+pub fn markdown_to_page_tokens(contents: &str, file_name: &String, diagnostics: &mut Vec<Diagnostic>) -> Option<Vec<PageToken>> {
+    let (front_matter, body) = split_front_matter(contents)?;
+    diagnostics.extend(check_front_matter(&front_matter, file_name));
+    let body_start = contents.len() - body.len();
+
+    let mut tokens = vec![PageToken {
+        token_type: "title".to_string(),
+        meta: front_matter.meta,
+    }];
+
+    let mut span = String::new();
+    // headers, links, images and code blocks swallow their text rather than it becoming spans
+    let mut captured: Option<String> = None;
+    let mut list_depth = 0;
+
+    for (event, range) in Parser::new_ext(body, Options::empty()).into_offset_iter() {
+        match event {
+            Event::Start(Tag::Heading(_)) | Event::Start(Tag::Link(..))
+            | Event::Start(Tag::Image(..)) | Event::Start(Tag::CodeBlock(_)) => {
+                finish_span(&mut span, &mut tokens);
+                captured = Some(String::new());
+            }
+            Event::End(Tag::Heading(_)) => {
+                let text = captured.take().unwrap_or_default();
+                tokens.push(page_token("header", vec![("header", "<empty>".to_string()), ("text", text)]));
+            }
+            Event::End(Tag::Link(_, url, _)) => {
+                let text = captured.take().unwrap_or_default();
+                tokens.push(page_token("url", vec![("url", url.to_string()), ("text", text)]));
+            }
+            Event::End(Tag::Image(_, url, title)) => {
+                let alt = captured.take().unwrap_or_default();
+                let mut image = page_token("image", vec![("image", url.to_string()), ("alt", alt)]);
+                // `![alt](src "title")`, the title is the closest thing markdown has to a caption
+                if !title.is_empty() {
+                    image.meta.insert("caption".to_string(), title.to_string());
+                }
+                tokens.push(image);
+            }
+            Event::End(Tag::CodeBlock(_)) => {
+                let text = captured.take().unwrap_or_default();
+                tokens.push(page_token("code_block", vec![("text", text)]));
+            }
+            Event::Start(Tag::List(start)) => {
+                finish_span(&mut span, &mut tokens);
+                let meta = if start.is_some() { vec![("ordered", "<empty>".to_string())] } else { vec![] };
+                tokens.push(page_token("list_start", meta));
+                list_depth += 1;
+            }
+            Event::End(Tag::List(_)) => {
+                tokens.push(page_token("list_end", vec![]));
+                list_depth -= 1;
+            }
+            Event::Start(Tag::BlockQuote) => {
+                finish_span(&mut span, &mut tokens);
+                tokens.push(page_token("quote_start", vec![("quote", "<empty>".to_string())]));
+            }
+            Event::End(Tag::BlockQuote) => {
+                finish_span(&mut span, &mut tokens);
+                tokens.push(page_token("quote_end", vec![]));
+            }
+            Event::Start(Tag::Item) => {
+                tokens.push(page_token("item_start", vec![]));
+            }
+            Event::End(Tag::Item) => {
+                finish_span(&mut span, &mut tokens);
+                tokens.push(page_token("item_end", vec![]));
+            }
+            // list items hold their text directly, even in markdown's loose lists
+            Event::Start(Tag::Paragraph) if list_depth > 0 => {}
+            Event::End(Tag::Paragraph) if list_depth > 0 => finish_span(&mut span, &mut tokens),
+            Event::Start(Tag::Paragraph) => {
+                tokens.push(page_token("para_start", vec![]));
+            }
+            Event::End(Tag::Paragraph) => {
+                finish_span(&mut span, &mut tokens);
+                tokens.push(page_token("para_end", vec![]));
+            }
+            // a link or header's text is plain, any emphasis in it is dropped rather than splitting it
+            Event::Start(Tag::Emphasis) | Event::End(Tag::Emphasis)
+            | Event::Start(Tag::Strong) | Event::End(Tag::Strong) if captured.is_some() => {}
+            Event::Start(Tag::Emphasis) => {
+                finish_span(&mut span, &mut tokens);
+                tokens.push(page_token("em_start", vec![]));
+            }
+            Event::End(Tag::Emphasis) => {
+                finish_span(&mut span, &mut tokens);
+                tokens.push(page_token("em_end", vec![]));
+            }
+            Event::Start(Tag::Strong) => {
+                finish_span(&mut span, &mut tokens);
+                tokens.push(page_token("strong_start", vec![]));
+            }
+            Event::End(Tag::Strong) => {
+                finish_span(&mut span, &mut tokens);
+                tokens.push(page_token("strong_end", vec![]));
+            }
+            Event::Text(text) => {
+                match captured.as_mut() {
+                    Some(c) => c.push_str(&text),
+                    None => span.push_str(&text),
+                }
+            }
+            Event::Code(text) => {
+                match captured.as_mut() {
+                    Some(c) => c.push_str(&text),
+                    None => {
+                        finish_span(&mut span, &mut tokens);
+                        tokens.push(page_token("code", vec![("text", text.to_string())]));
+                    }
+                }
+            }
+            Event::SoftBreak => {
+                match captured.as_mut() {
+                    Some(c) => c.push(' '),
+                    None => span.push(' '),
+                }
+            }
+            Event::HardBreak => {
+                finish_span(&mut span, &mut tokens);
+                tokens.push(page_token("line_break", vec![]));
+            }
+            Event::Rule => {
+                tokens.push(page_token("rule", vec![]));
+            }
+            Event::Html(_) => {
+                let (line, column) = diagnostics::source_position(contents, body_start + range.start);
+                diagnostics.push(Diagnostic {
+                    severity: Severity::Warning,
+                    file: file_name.clone(),
+                    line,
+                    column,
+                    message: String::from("raw HTML isn't supported, it was left out"),
+                });
+            }
+            _ => {}
+        }
+    }
+    finish_span(&mut span, &mut tokens);
+
+    return Some(tokens);
+}
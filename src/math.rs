@@ -0,0 +1,488 @@
+use crate::render::escape_html;
+
+// a small TeX to MathML converter, enough for the formulas that turn up in posts: scripts,
+// fractions, roots, greek, the usual operators and relations, \left...\right, \text and friends.
+// it runs while compiling so pages don't need a math library in the browser
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(mathml: &str) -> &str {
+        let start = mathml.find("<semantics>").unwrap() + "<semantics>".len();
+        let end = mathml.find("<annotation").unwrap();
+        return &mathml[start..end];
+    }
+
+    #[test]
+    fn formulas_become_mathml() {
+        let mathml = tex_to_mathml(r"x^2 + \frac{a}{\sqrt[3]{b}} \leq \alpha_{i,j}", false).unwrap();
+        assert!(mathml.starts_with("<math xmlns=\"http://www.w3.org/1998/Math/MathML\" display=\"inline\">"));
+        assert_eq!(body(&mathml), "<mrow><msup><mi>x</mi><mn>2</mn></msup><mo>+</mo>\
+            <mfrac><mi>a</mi><mroot><mi>b</mi><mn>3</mn></mroot></mfrac><mo>≤</mo>\
+            <msub><mi>α</mi><mrow><mi>i</mi><mo>,</mo><mi>j</mi></mrow></msub></mrow>");
+
+        // big operators take their limits above and below in display math, numbers only give
+        // their first digit to a script like TeX does
+        let mathml = tex_to_mathml(r"\sum_{n=1}^\infty 2^10", true).unwrap();
+        assert_eq!(body(&mathml), "<mrow><munderover><mo>∑</mo><mrow><mi>n</mi><mo>=</mo><mn>1</mn></mrow><mi>∞</mi></munderover>\
+            <msup><mn>2</mn><mn>1</mn></msup><mn>0</mn></mrow>");
+    }
+
+    #[test]
+    fn errors_point_into_the_formula() {
+        let error = |tex: &str| tex_to_mathml(tex, false).unwrap_err();
+
+        assert_eq!(error(r"\frac{a}{b"), MathError { offset: 8, message: String::from("'{' is never closed") });
+        assert_eq!(error(r"a + \foo").offset, 4);
+        assert_eq!(error(r"x^").message, "expected something after '^'");
+        assert_eq!(error(r"x^a^b").offset, 3);
+        assert_eq!(error(r"\left( x").offset, 0);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MathError {
+    // bytes into the TeX
+    pub offset: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Command(String),
+    Letter(char),
+    Number(String),
+    Symbol(char),
+    Open,
+    Close,
+    Sup,
+    Sub,
+}
+
+const GREEK: &[(&str, &str)] = &[
+    ("alpha", "α"), ("beta", "β"), ("gamma", "γ"), ("delta", "δ"), ("epsilon", "ϵ"), ("varepsilon", "ε"),
+    ("zeta", "ζ"), ("eta", "η"), ("theta", "θ"), ("vartheta", "ϑ"), ("iota", "ι"), ("kappa", "κ"),
+    ("lambda", "λ"), ("mu", "μ"), ("nu", "ν"), ("xi", "ξ"), ("pi", "π"), ("varpi", "ϖ"), ("rho", "ρ"),
+    ("varrho", "ϱ"), ("sigma", "σ"), ("varsigma", "ς"), ("tau", "τ"), ("upsilon", "υ"), ("phi", "ϕ"),
+    ("varphi", "φ"), ("chi", "χ"), ("psi", "ψ"), ("omega", "ω"), ("Gamma", "Γ"), ("Delta", "Δ"),
+    ("Theta", "Θ"), ("Lambda", "Λ"), ("Xi", "Ξ"), ("Pi", "Π"), ("Sigma", "Σ"), ("Upsilon", "Υ"),
+    ("Phi", "Φ"), ("Psi", "Ψ"), ("Omega", "Ω"),
+    // not greek, but they're identifiers too
+    ("infty", "∞"), ("partial", "∂"), ("nabla", "∇"), ("emptyset", "∅"), ("varnothing", "∅"),
+    ("hbar", "ℏ"), ("ell", "ℓ"), ("aleph", "ℵ"), ("Re", "ℜ"), ("Im", "ℑ"),
+];
+
+const OPERATORS: &[(&str, &str)] = &[
+    ("pm", "±"), ("mp", "∓"), ("times", "×"), ("div", "÷"), ("cdot", "⋅"), ("ast", "∗"), ("star", "⋆"),
+    ("circ", "∘"), ("bullet", "∙"), ("oplus", "⊕"), ("otimes", "⊗"), ("cap", "∩"), ("cup", "∪"),
+    ("setminus", "∖"), ("wedge", "∧"), ("land", "∧"), ("vee", "∨"), ("lor", "∨"), ("neg", "¬"), ("lnot", "¬"),
+    ("leq", "≤"), ("le", "≤"), ("geq", "≥"), ("ge", "≥"), ("neq", "≠"), ("ne", "≠"), ("approx", "≈"),
+    ("equiv", "≡"), ("sim", "∼"), ("simeq", "≃"), ("cong", "≅"), ("propto", "∝"), ("ll", "≪"), ("gg", "≫"),
+    ("in", "∈"), ("notin", "∉"), ("ni", "∋"), ("subset", "⊂"), ("supset", "⊃"), ("subseteq", "⊆"),
+    ("supseteq", "⊇"), ("mid", "∣"), ("parallel", "∥"), ("perp", "⊥"), ("to", "→"), ("rightarrow", "→"),
+    ("leftarrow", "←"), ("gets", "←"), ("Rightarrow", "⇒"), ("Leftarrow", "⇐"), ("leftrightarrow", "↔"),
+    ("Leftrightarrow", "⇔"), ("iff", "⟺"), ("implies", "⟹"), ("mapsto", "↦"), ("uparrow", "↑"),
+    ("downarrow", "↓"), ("forall", "∀"), ("exists", "∃"), ("ldots", "…"), ("cdots", "⋯"), ("dots", "…"),
+    ("vdots", "⋮"), ("ddots", "⋱"), ("langle", "⟨"), ("rangle", "⟩"), ("lfloor", "⌊"), ("rfloor", "⌋"),
+    ("lceil", "⌈"), ("rceil", "⌉"), ("vert", "|"), ("Vert", "‖"), ("colon", ":"), ("prime", "′"),
+    ("angle", "∠"), ("triangle", "△"), ("therefore", "∴"), ("because", "∵"), ("lbrack", "["),
+    ("rbrack", "]"), ("lbrace", "{"), ("rbrace", "}"), ("int", "∫"), ("iint", "∬"), ("oint", "∮"),
+    ("{", "{"), ("}", "}"), ("|", "‖"), ("%", "%"), ("$", "$"), ("#", "#"), ("&", "&"), ("_", "_"),
+];
+
+// these take their limits above and below in display math
+const LARGE_OPERATORS: &[(&str, &str)] = &[
+    ("sum", "∑"), ("prod", "∏"), ("coprod", "∐"), ("bigcup", "⋃"), ("bigcap", "⋂"), ("bigoplus", "⨁"),
+    ("bigotimes", "⨂"),
+];
+
+const FUNCTIONS: &[&str] = &[
+    "sin", "cos", "tan", "sec", "csc", "cot", "arcsin", "arccos", "arctan", "sinh", "cosh", "tanh",
+    "log", "ln", "lg", "exp", "deg", "dim", "ker", "arg", "hom",
+];
+
+// functions which also take their limits underneath, like lim_{x \to 0}
+const LIMIT_FUNCTIONS: &[&str] = &["lim", "liminf", "limsup", "max", "min", "sup", "inf", "det", "gcd", "Pr"];
+
+const SPACES: &[(&str, &str)] = &[
+    (",", "0.1667em"), (":", "0.2222em"), (">", "0.2222em"), (";", "0.2778em"), (" ", "0.3333em"),
+    ("quad", "1em"), ("qquad", "2em"), ("!", "-0.1667em"),
+];
+
+const ACCENTS: &[(&str, &str)] = &[
+    ("hat", "^"), ("widehat", "^"), ("bar", "¯"), ("overline", "‾"), ("vec", "→"), ("dot", "˙"),
+    ("ddot", "¨"), ("tilde", "˜"), ("widetilde", "˜"),
+];
+
+const DOUBLE_STRUCK: &[(char, char)] = &[('C', 'ℂ'), ('N', 'ℕ'), ('P', 'ℙ'), ('Q', 'ℚ'), ('R', 'ℝ'), ('Z', 'ℤ')];
+
+fn lookup(table: &[(&str, &'static str)], name: &str) -> Option<&'static str> {
+    return table.iter().find(|(n, _)| *n == name).map(|(_, s)| *s);
+}
+
+fn error<T>(offset: usize, message: String) -> Result<T, MathError> {
+    return Err(MathError { offset, message });
+}
+
+fn tokenize(tex: &str) -> Result<Vec<(usize, Tok)>, MathError> {
+    let mut tokens: Vec<(usize, Tok)> = Vec::new();
+    let mut chars = tex.char_indices().peekable();
+
+    while let Some((offset, ch)) = chars.next() {
+        let token = match ch {
+            c if c.is_whitespace() => continue,
+            '\\' => {
+                let mut name = String::new();
+                while let Some((_, c)) = chars.peek() {
+                    if !c.is_ascii_alphabetic() { break; }
+                    name.push(*c);
+                    chars.next();
+                }
+                // otherwise it's a single character like \{ or \,
+                if name.is_empty() {
+                    match chars.next() {
+                        Some((_, c)) => name.push(c),
+                        None => return error(offset, String::from("'\\' at the end of the formula")),
+                    }
+                }
+                Tok::Command(name)
+            }
+            '{' => Tok::Open,
+            '}' => Tok::Close,
+            '^' => Tok::Sup,
+            '_' => Tok::Sub,
+            '~' => Tok::Command(String::from(" ")),
+            '%' => {
+                // a comment, up to the end of the line
+                while let Some((_, c)) = chars.next() {
+                    if c == '\n' { break; }
+                }
+                continue;
+            }
+            '&' | '#' | '$' => return error(offset, format!("'{}' isn't supported in a formula", ch)),
+            c if c.is_ascii_digit() => {
+                let mut number = c.to_string();
+                while let Some((_, c)) = chars.peek() {
+                    if !c.is_ascii_digit() && *c != '.' { break; }
+                    number.push(*c);
+                    chars.next();
+                }
+                Tok::Number(number)
+            }
+            c if c.is_alphabetic() => Tok::Letter(c),
+            c => Tok::Symbol(c),
+        };
+        tokens.push((offset, token));
+    }
+
+    return Ok(tokens);
+}
+
+// what ends a row of atoms
+#[derive(Clone, Copy, PartialEq)]
+enum Until {
+    End,
+    // a `}`, the offset is where its `{` was
+    Close(usize),
+    // a `]` ending \sqrt's index
+    Bracket(usize),
+    // \right, the offset is where its \left was
+    Right(usize),
+}
+
+struct Atom {
+    xml: String,
+    // limits go above and below rather than to the side in display math
+    limits: bool,
+}
+
+impl Atom {
+    fn new(xml: String) -> Atom {
+        Atom { xml, limits: false }
+    }
+}
+
+struct Parser<'a> {
+    tex: &'a str,
+    tokens: Vec<(usize, Tok)>,
+    pos: usize,
+    display: bool,
+}
+
+fn mrow(items: Vec<String>) -> String {
+    if items.len() == 1 {
+        return items.into_iter().next().unwrap();
+    }
+    return format!("<mrow>{}</mrow>", items.concat());
+}
+
+fn mo(symbol: &str) -> String {
+    return format!("<mo>{}</mo>", escape_html(symbol));
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Tok> {
+        return self.tokens.get(self.pos).map(|(_, t)| t);
+    }
+
+    // where the next token starts, or the end of the formula
+    fn offset(&self) -> usize {
+        return self.tokens.get(self.pos).map(|(o, _)| *o).unwrap_or(self.tex.len());
+    }
+
+    fn next(&mut self) -> Option<(usize, Tok)> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        return token;
+    }
+
+    fn parse_row(&mut self, until: Until) -> Result<Vec<String>, MathError> {
+        let mut items: Vec<String> = Vec::new();
+        // whether the last atom takes limits, like \sum
+        let mut limits = false;
+
+        loop {
+            let offset = self.offset();
+            match (self.peek(), until) {
+                (None, Until::End) => break,
+                (None, Until::Close(open)) => return error(open, String::from("'{' is never closed")),
+                (None, Until::Bracket(open)) => return error(open, String::from("'[' is never closed")),
+                (None, Until::Right(left)) => return error(left, String::from("\\left without a matching \\right")),
+                (Some(Tok::Close), Until::Close(_)) => {
+                    self.pos += 1;
+                    break;
+                }
+                (Some(Tok::Close), _) => return error(offset, String::from("'}' without a matching '{'")),
+                (Some(Tok::Symbol(']')), Until::Bracket(_)) => {
+                    self.pos += 1;
+                    break;
+                }
+                (Some(Tok::Command(name)), Until::Right(_)) if name == "right" => break,
+                (Some(Tok::Sup), _) | (Some(Tok::Sub), _) => {
+                    // scripts on nothing at all hang off an empty base
+                    let base = items.pop().unwrap_or(String::from("<mrow></mrow>"));
+                    let scripts = self.parse_scripts()?;
+                    items.push(self.attach(base, scripts, limits));
+                    limits = false;
+                }
+                _ => {
+                    let atom = self.parse_atom()?;
+                    limits = atom.limits;
+                    items.push(atom.xml);
+                }
+            }
+        }
+
+        return Ok(items);
+    }
+
+    // one or both of ^ and _, in either order. returns (sub, sup)
+    fn parse_scripts(&mut self) -> Result<(Option<String>, Option<String>), MathError> {
+        let mut sub: Option<String> = None;
+        let mut sup: Option<String> = None;
+
+        while let Some(token) = self.peek() {
+            let superscript = match token {
+                Tok::Sup => true,
+                Tok::Sub => false,
+                _ => break,
+            };
+            let (offset, _) = self.next().unwrap();
+            let slot = if superscript { &sup } else { &sub };
+            if slot.is_some() {
+                let which = if superscript { "superscript" } else { "subscript" };
+                return error(offset, format!("double {}, use braces to say what goes where", which));
+            }
+
+            let arg = self.parse_argument(offset, if superscript { "'^'" } else { "'_'" })?;
+            if superscript { sup = Some(arg); } else { sub = Some(arg); }
+        }
+
+        return Ok((sub, sup));
+    }
+
+    fn attach(&self, base: String, scripts: (Option<String>, Option<String>), limits: bool) -> String {
+        let under = limits && self.display;
+        return match scripts {
+            (Some(sub), Some(sup)) if under => format!("<munderover>{}{}{}</munderover>", base, sub, sup),
+            (Some(sub), Some(sup)) => format!("<msubsup>{}{}{}</msubsup>", base, sub, sup),
+            (Some(sub), None) if under => format!("<munder>{}{}</munder>", base, sub),
+            (Some(sub), None) => format!("<msub>{}{}</msub>", base, sub),
+            (None, Some(sup)) if under => format!("<mover>{}{}</mover>", base, sup),
+            (None, Some(sup)) => format!("<msup>{}{}</msup>", base, sup),
+            (None, None) => base,
+        };
+    }
+
+    // the argument to a script or a command: a group, or a single token. a number only gives up
+    // its first digit, so x^10 is x squared... then a zero, same as TeX
+    fn parse_argument(&mut self, offset: usize, what: &str) -> Result<String, MathError> {
+        match self.tokens.get(self.pos).cloned() {
+            None | Some((_, Tok::Close)) | Some((_, Tok::Sup)) | Some((_, Tok::Sub)) => {
+                return error(offset, format!("expected something after {}", what));
+            }
+            Some((number_offset, Tok::Number(number))) if number.len() > 1 => {
+                let (first, rest) = number.split_at(1);
+                self.tokens[self.pos] = (number_offset + 1, Tok::Number(rest.to_string()));
+                return Ok(format!("<mn>{}</mn>", first));
+            }
+            _ => return Ok(self.parse_atom()?.xml),
+        }
+    }
+
+    // the source between a `{` and its `}`, for commands like \text which take their argument as is
+    fn parse_raw_group(&mut self, offset: usize, command: &str) -> Result<String, MathError> {
+        let open = match self.next() {
+            Some((open, Tok::Open)) => open,
+            _ => return error(offset, format!("\\{} needs its argument in braces", command)),
+        };
+
+        let mut depth = 0;
+        while let Some((close, token)) = self.next() {
+            match token {
+                Tok::Open => depth += 1,
+                Tok::Close if depth == 0 => return Ok(self.tex[open + 1..close].to_string()),
+                Tok::Close => depth -= 1,
+                _ => {}
+            }
+        }
+        return error(open, String::from("'{' is never closed"));
+    }
+
+    fn parse_delimiter(&mut self, offset: usize, command: &str) -> Result<String, MathError> {
+        let delimiter = match self.next() {
+            Some((_, Tok::Symbol('.'))) => return Ok(String::new()),
+            Some((_, Tok::Symbol(c))) if "()[]|/".contains(c) => c.to_string(),
+            Some((_, Tok::Symbol('<'))) => String::from("⟨"),
+            Some((_, Tok::Symbol('>'))) => String::from("⟩"),
+            Some((_, Tok::Command(name))) if ["{", "}", "|", "langle", "rangle", "lfloor", "rfloor", "lceil", "rceil", "vert", "Vert"].contains(&name.as_str()) => {
+                lookup(OPERATORS, &name).unwrap().to_string()
+            }
+            _ => return error(offset, format!("expected a delimiter after \\{}", command)),
+        };
+        return Ok(format!("<mo fence=\"true\" stretchy=\"true\">{}</mo>", escape_html(&delimiter)));
+    }
+
+    fn parse_atom(&mut self) -> Result<Atom, MathError> {
+        let (offset, token) = match self.next() {
+            Some(token) => token,
+            None => return error(self.tex.len(), String::from("the formula ended early")),
+        };
+
+        return match token {
+            Tok::Letter(c) => Ok(Atom::new(format!("<mi>{}</mi>", c))),
+            Tok::Number(n) => Ok(Atom::new(format!("<mn>{}</mn>", n))),
+            Tok::Symbol(c) => {
+                let symbol = match c {
+                    '-' => String::from("−"),
+                    '*' => String::from("∗"),
+                    '\'' => String::from("′"),
+                    c => c.to_string(),
+                };
+                Ok(Atom::new(mo(&symbol)))
+            }
+            Tok::Open => Ok(Atom::new(mrow(self.parse_row(Until::Close(offset))?))),
+            Tok::Command(name) => self.parse_command(offset, &name),
+            Tok::Close | Tok::Sup | Tok::Sub => error(offset, String::from("unexpected token")),
+        };
+    }
+
+    fn parse_command(&mut self, offset: usize, name: &str) -> Result<Atom, MathError> {
+        if let Some(symbol) = lookup(GREEK, name) {
+            return Ok(Atom::new(format!("<mi>{}</mi>", symbol)));
+        }
+        if let Some(symbol) = lookup(OPERATORS, name) {
+            return Ok(Atom::new(mo(symbol)));
+        }
+        if let Some(symbol) = lookup(LARGE_OPERATORS, name) {
+            return Ok(Atom { xml: mo(symbol), limits: true });
+        }
+        if FUNCTIONS.contains(&name) {
+            return Ok(Atom::new(format!("<mi>{}</mi>", name)));
+        }
+        if LIMIT_FUNCTIONS.contains(&name) {
+            return Ok(Atom { xml: format!("<mi>{}</mi>", name), limits: true });
+        }
+        if let Some(width) = lookup(SPACES, name) {
+            return Ok(Atom::new(format!("<mspace width=\"{}\"/>", width)));
+        }
+        if let Some(accent) = lookup(ACCENTS, name) {
+            let arg = self.parse_argument(offset, &format!("\\{}", name))?;
+            return Ok(Atom::new(format!("<mover accent=\"true\">{}{}</mover>", arg, mo(accent))));
+        }
+
+        let command = format!("\\{}", name);
+        let xml = match name {
+            "frac" | "dfrac" | "tfrac" => {
+                let numerator = self.parse_argument(offset, &command)?;
+                let denominator = self.parse_argument(offset, &command)?;
+                format!("<mfrac>{}{}</mfrac>", numerator, denominator)
+            }
+            "binom" => {
+                let n = self.parse_argument(offset, &command)?;
+                let k = self.parse_argument(offset, &command)?;
+                format!("<mrow><mo>(</mo><mfrac linethickness=\"0\">{}{}</mfrac><mo>)</mo></mrow>", n, k)
+            }
+            "sqrt" => {
+                if self.peek() == Some(&Tok::Symbol('[')) {
+                    let (open, _) = self.next().unwrap();
+                    let index = mrow(self.parse_row(Until::Bracket(open))?);
+                    let radicand = self.parse_argument(offset, &command)?;
+                    format!("<mroot>{}{}</mroot>", radicand, index)
+                } else {
+                    format!("<msqrt>{}</msqrt>", self.parse_argument(offset, &command)?)
+                }
+            }
+            "underline" => format!("<munder accent=\"true\">{}<mo>_</mo></munder>", self.parse_argument(offset, &command)?),
+            "text" | "textrm" | "mbox" => format!("<mtext>{}</mtext>", escape_html(&self.parse_raw_group(offset, name)?)),
+            "operatorname" => format!("<mi>{}</mi>", escape_html(self.parse_raw_group(offset, name)?.trim())),
+            "mathrm" | "mathbf" | "mathit" | "mathsf" | "mathtt" | "mathcal" | "mathbb" => {
+                let text = self.parse_raw_group(offset, name)?;
+                let text = text.trim();
+                let double_struck = DOUBLE_STRUCK.iter().find(|(c, _)| text.len() == 1 && text.starts_with(*c));
+                match (name, double_struck) {
+                    ("mathbb", Some((_, symbol))) => format!("<mi>{}</mi>", symbol),
+                    _ => {
+                        let variant = match name {
+                            "mathrm" => "normal",
+                            "mathbf" => "bold",
+                            "mathit" => "italic",
+                            "mathsf" => "sans-serif",
+                            "mathtt" => "monospace",
+                            "mathcal" => "script",
+                            _ => "double-struck",
+                        };
+                        format!("<mi mathvariant=\"{}\">{}</mi>", variant, escape_html(text))
+                    }
+                }
+            }
+            "left" => {
+                let open = self.parse_delimiter(offset, name)?;
+                let body = self.parse_row(Until::Right(offset))?;
+                let (right, _) = self.next().unwrap();
+                let close = self.parse_delimiter(right, "right")?;
+                format!("<mrow>{}{}{}</mrow>", open, body.concat(), close)
+            }
+            "right" => return error(offset, String::from("\\right without a matching \\left")),
+            "\\" => return error(offset, String::from("line breaks aren't supported, use separate formulas")),
+            _ => return error(offset, format!("unknown command {}", command)),
+        };
+        return Ok(Atom::new(xml));
+    }
+}
+
+// display math is centered on its own line, inline math sits in the text. the TeX is kept as an
+// annotation so copying the formula still gives something useful
+pub fn tex_to_mathml(tex: &str, display: bool) -> Result<String, MathError> {
+    let mut parser = Parser { tex, tokens: tokenize(tex)?, pos: 0, display };
+    let row = parser.parse_row(Until::End)?;
+
+    return Ok(format!(
+        "<math xmlns=\"http://www.w3.org/1998/Math/MathML\" display=\"{}\"><semantics><mrow>{}</mrow>\
+        <annotation encoding=\"application/x-tex\">{}</annotation></semantics></math>",
+        if display { "block" } else { "inline" },
+        row.concat(),
+        escape_html(tex.trim()),
+    ));
+}
@@ -0,0 +1,284 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+// what the site-content route needs to serve a post's files: their content type and which bytes
+// of them a `Range` header asks for, so browsers can seek through video and audio. also how big
+// an image is, which the compiler puts on the page
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn types_come_from_the_extension() {
+        assert_eq!(mime_type(Path::new("clips/go.MP4")), "video/mp4");
+        assert_eq!(mime_type(Path::new("talk.ogg")), "audio/ogg");
+        assert_eq!(mime_type(Path::new("imgs/board.png")), "image/png");
+        assert_eq!(mime_type(Path::new("notes")), "application/octet-stream");
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range(None, 100), ByteRange::Whole);
+        assert_eq!(parse_range(Some("bytes=0-"), 100), ByteRange::Partial(0, 99));
+        assert_eq!(parse_range(Some("bytes=10-19"), 100), ByteRange::Partial(10, 19));
+        assert_eq!(parse_range(Some("bytes=90-200"), 100), ByteRange::Partial(90, 99));
+        assert_eq!(parse_range(Some("bytes=-10"), 100), ByteRange::Partial(90, 99));
+        assert_eq!(parse_range(Some("bytes=-500"), 100), ByteRange::Partial(0, 99));
+        assert_eq!(parse_range(Some("bytes=100-"), 100), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=-0"), 100), ByteRange::Unsatisfiable);
+        // anything we don't understand gets the whole file, which is always allowed
+        assert_eq!(parse_range(Some("bytes=20-10"), 100), ByteRange::Whole);
+        assert_eq!(parse_range(Some("bytes=0-1,5-6"), 100), ByteRange::Whole);
+        assert_eq!(parse_range(Some("lines=1-2"), 100), ByteRange::Whole);
+    }
+
+    #[test]
+    fn image_sizes_come_from_the_header() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend_from_slice(&[0, 0, 2, 128, 0, 0, 1, 224]);
+        assert_eq!(image_size(&png), Some((640, 480)));
+
+        assert_eq!(image_size(b"GIF89a\x20\x03\x58\x02"), Some((800, 600)));
+
+        // an APP0 segment to skip over before the frame
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe0, 0, 4, 0, 0];
+        jpeg.extend_from_slice(&[0xff, 0xc0, 0, 17, 8, 1, 44, 1, 144]);
+        assert_eq!(image_size(&jpeg), Some((400, 300)));
+
+        let mut webp = b"RIFF\0\0\0\0WEBPVP8X\0\0\0\0\0\0\0\0".to_vec();
+        webp.extend_from_slice(&[0xff, 0x03, 0, 0x1f, 0x03, 0]);
+        assert_eq!(image_size(&webp), Some((1024, 800)));
+
+        assert_eq!(image_size(b"<svg></svg>"), None);
+        assert_eq!(image_size(&[0xff, 0xd8, 0xff]), None);
+    }
+
+    #[test]
+    fn only_the_start_of_an_image_file_is_read() {
+        let dir = std::env::temp_dir().join(format!("simple-blog-media-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend_from_slice(&[0, 0, 2, 128, 0, 0, 1, 224]);
+        png.resize(IMAGE_HEADER_LIMIT as usize * 4, 0);
+        std::fs::write(dir.join("big.png"), &png).unwrap();
+        assert_eq!(read_image_size(&dir.join("big.png")), Some((640, 480)));
+
+        // a frame past the limit, e.g. behind a huge exif segment, just means no size
+        let mut jpeg = vec![0xff, 0xd8];
+        for _ in 0..2 {
+            jpeg.extend_from_slice(&[0xff, 0xe1, 0xff, 0xff]);
+            jpeg.resize(jpeg.len() + 0xfffd, 0);
+        }
+        jpeg.extend_from_slice(&[0xff, 0xc0, 0, 17, 8, 1, 44, 1, 144]);
+        assert_eq!(image_size(&jpeg), Some((400, 300)));
+        std::fs::write(dir.join("exif.jpg"), &jpeg).unwrap();
+        assert_eq!(read_image_size(&dir.join("exif.jpg")), None);
+
+        assert_eq!(read_image_size(&dir.join("missing.png")), None);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn post_files_stay_in_the_post_directory() {
+        let dir = std::env::temp_dir().join(format!("simple-blog-post-file-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("post/imgs")).unwrap();
+        std::fs::write(dir.join("post/imgs/a.png"), "").unwrap();
+        std::fs::write(dir.join("secret.png"), "").unwrap();
+        let post_dir = dir.join("post");
+
+        assert_eq!(post_file(&post_dir, "./imgs/a.png"), Ok(std::fs::canonicalize(dir.join("post/imgs/a.png")).unwrap()));
+        assert!(post_file(&post_dir, "imgs/../../secret.png").unwrap_err().contains("outside the post's directory"));
+        assert!(post_file(&post_dir, dir.join("secret.png").to_str().unwrap()).unwrap_err().contains("outside the post's directory"));
+        assert!(post_file(&post_dir, "missing.png").unwrap_err().starts_with("couldn't read 'missing.png'"));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
+
+// anything we don't know about is sent as plain bytes
+pub fn mime_type(path: &Path) -> &'static str {
+    let extension = path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+        .unwrap_or_default();
+
+    return match extension.as_str() {
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "ogv" => "video/ogg",
+        "mov" => "video/quicktime",
+        "mp3" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "ogg" | "oga" | "opus" => "audio/ogg",
+        "wav" => "audio/wav",
+        "flac" => "audio/flac",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "svg" => "image/svg+xml",
+        "vtt" => "text/vtt",
+        "csv" => "text/csv; charset=utf-8",
+        "txt" => "text/plain; charset=utf-8",
+        "json" => "application/json",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    };
+}
+
+// `Partial` holds the first and last byte, both included, like the Content-Range header
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ByteRange {
+    Whole,
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+// the value of a `Range` header for a file `len` bytes long. only single byte ranges are handled,
+// for anything else the whole file is sent, which the spec allows
+pub fn parse_range(header: Option<&str>, len: u64) -> ByteRange {
+    let spec = match header.and_then(|h| h.trim().strip_prefix("bytes=")) {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return ByteRange::Whole,
+    };
+    let (start, end) = match spec.find('-') {
+        Some(dash) => (spec[..dash].trim(), spec[dash + 1..].trim()),
+        None => return ByteRange::Whole,
+    };
+
+    // `bytes=-500` is the last 500 bytes
+    if start.is_empty() {
+        return match end.parse::<u64>() {
+            Ok(0) => ByteRange::Unsatisfiable,
+            Ok(_) if len == 0 => ByteRange::Unsatisfiable,
+            Ok(suffix) => ByteRange::Partial(len.saturating_sub(suffix), len - 1),
+            Err(_) => ByteRange::Whole,
+        };
+    }
+
+    let start: u64 = match start.parse() {
+        Ok(start) => start,
+        Err(_) => return ByteRange::Whole,
+    };
+    let end: u64 = match end {
+        "" => u64::MAX,
+        end => match end.parse() {
+            Ok(end) => end,
+            Err(_) => return ByteRange::Whole,
+        },
+    };
+
+    if end < start {
+        return ByteRange::Whole;
+    }
+    if start >= len {
+        return ByteRange::Unsatisfiable;
+    }
+    return ByteRange::Partial(start, end.min(len - 1));
+}
+
+fn be16(bytes: &[u8], at: usize) -> Option<u32> {
+    let b = bytes.get(at..at + 2)?;
+    return Some(u16::from_be_bytes([b[0], b[1]]) as u32);
+}
+
+fn be32(bytes: &[u8], at: usize) -> Option<u32> {
+    let b = bytes.get(at..at + 4)?;
+    return Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+}
+
+fn le16(bytes: &[u8], at: usize) -> Option<u32> {
+    let b = bytes.get(at..at + 2)?;
+    return Some(u16::from_le_bytes([b[0], b[1]]) as u32);
+}
+
+fn le24(bytes: &[u8], at: usize) -> Option<u32> {
+    let b = bytes.get(at..at + 3)?;
+    return Some(u32::from_le_bytes([b[0], b[1], b[2], 0]));
+}
+
+// width and height in pixels, for the formats browsers show. None for anything we can't read,
+// e.g. svg, which scales to fit anyway
+pub fn image_size(bytes: &[u8]) -> Option<(u32, u32)> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        // the IHDR chunk always comes first
+        return Some((be32(bytes, 16)?, be32(bytes, 20)?));
+    }
+    if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        return Some((le16(bytes, 6)?, le16(bytes, 8)?));
+    }
+    if bytes.starts_with(&[0xff, 0xd8]) {
+        return jpeg_size(bytes);
+    }
+    if bytes.get(0..4) == Some(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+        return webp_size(bytes);
+    }
+    return None;
+}
+
+// a file a post points at, which has to be somewhere under the post's own directory. absolute
+// paths and `..` are fine as long as they end up there
+pub fn post_file(post_dir: &Path, file: &str) -> Result<PathBuf, String> {
+    let path = fs::canonicalize(post_dir.join(file)).map_err(|e| format!("couldn't read '{}': {}", file, e))?;
+    let post_dir = fs::canonicalize(post_dir).map_err(|e| format!("couldn't read '{}': {}", file, e))?;
+    if !path.starts_with(&post_dir) {
+        return Err(format!("'{}' is outside the post's directory", file));
+    }
+    return Ok(path);
+}
+
+// the size is always near the start, so big photos aren't read in whole just for it
+const IMAGE_HEADER_LIMIT: u64 = 64 * 1024;
+
+// like image_size, for a file. None if it can't be read or the size isn't in its first 64 KiB
+pub fn read_image_size(path: &Path) -> Option<(u32, u32)> {
+    let mut bytes = Vec::new();
+    File::open(path).ok()?.take(IMAGE_HEADER_LIMIT).read_to_end(&mut bytes).ok()?;
+    return image_size(&bytes);
+}
+
+// walks the segments up to the start of the frame, which has the size in it
+fn jpeg_size(bytes: &[u8]) -> Option<(u32, u32)> {
+    let mut at = 2;
+    loop {
+        if *bytes.get(at)? != 0xff {
+            return None;
+        }
+        // any extra 0xff bytes before a marker are padding
+        let mut marker = *bytes.get(at + 1)?;
+        while marker == 0xff {
+            at += 1;
+            marker = *bytes.get(at + 1)?;
+        }
+        at += 2;
+
+        match marker {
+            // markers without a segment
+            0x01 | 0xd0..=0xd9 => {}
+            // start of frame: length, precision, height, width. c4, c8 and cc aren't frames
+            0xc0..=0xcf if ![0xc4, 0xc8, 0xcc].contains(&marker) => {
+                return Some((be16(bytes, at + 5)?, be16(bytes, at + 3)?));
+            }
+            _ => at += be16(bytes, at)? as usize,
+        }
+    }
+}
+
+fn webp_size(bytes: &[u8]) -> Option<(u32, u32)> {
+    return match bytes.get(12..16)? {
+        // lossy, the size is in the key frame header
+        b"VP8 " => Some((le16(bytes, 26)? & 0x3fff, le16(bytes, 28)? & 0x3fff)),
+        // lossless, 14 bits each less one
+        b"VP8L" => {
+            let bits = le16(bytes, 21)? | le16(bytes, 23)? << 16;
+            Some((1 + (bits & 0x3fff), 1 + ((bits >> 14) & 0x3fff)))
+        }
+        // extended, the canvas size less one
+        b"VP8X" => Some((1 + le24(bytes, 24)?, 1 + le24(bytes, 27)?)),
+        _ => None,
+    };
+}
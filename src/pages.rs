@@ -0,0 +1,182 @@
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::ast::{Block, Document};
+use crate::config::{self, SiteSettings};
+use crate::render;
+use crate::search::{self, SearchResult};
+use crate::site_cache;
+use crate::tokens::{PageToken, SiteContent};
+
+// the context every page template is rendered with, shared by the server and the static build
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket_contrib::templates::tera::Tera;
+
+    fn token(token_type: &str, meta: Vec<(&str, &str)>) -> PageToken {
+        PageToken {
+            token_type: token_type.to_string(),
+            meta: meta.into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        }
+    }
+
+    // named the way rocket names them, after the file without its extensions
+    fn render_template(name: &str, context: &RenderedPage) -> String {
+        let sources: Vec<(String, String)> = ["base", "post", "partials/body"].iter()
+            .map(|name| (name.to_string(), std::fs::read_to_string(format!("templates/{}.html.tera", name)).unwrap()))
+            .collect();
+        let mut tera = Tera::default();
+        tera.add_raw_templates(sources.iter().map(|(name, source)| (name.as_str(), source.as_str())).collect()).unwrap();
+        return tera.render(name, context).unwrap();
+    }
+
+    #[test]
+    fn footnotes_link_within_the_post_page() {
+        let document = Document::from_page_tokens(&[
+            token("para_start", vec![]),
+            token("span", vec![("text", "Text")]),
+            token("fn", vec![("fn", "A note"), ("number", "1"), ("anchor", "h-1")]),
+            token("para_end", vec![]),
+            token("footnotes_start", vec![]),
+            token("footnote", vec![("text", "A note"), ("number", "1"), ("anchor", "h-1")]),
+            token("footnotes_end", vec![]),
+        ]);
+        let html = render_template("post", &RenderedPage::new(document));
+
+        // a <base> would send fragments off to the page it points at instead
+        assert!(!html.contains("<base"));
+        assert!(html.contains("href=\"#fn-h-1\"") && html.contains("id=\"fn-h-1\""));
+        assert!(html.contains("href=\"#fnref-h-1\"") && html.contains("id=\"fnref-h-1\""));
+    }
+
+    #[test]
+    fn posts_are_split_into_pages() {
+        assert_eq!(page_count(0, 5), 1);
+        assert_eq!(page_count(12, 0), 1);
+        assert_eq!(page_count(10, 5), 2);
+        assert_eq!(page_count(11, 5), 3);
+
+        let posts: Vec<usize> = (1..=11).collect();
+        assert_eq!(posts_on_page(posts.clone(), 1, 5), vec![1, 2, 3, 4, 5]);
+        assert_eq!(posts_on_page(posts.clone(), 3, 5), vec![11]);
+        assert_eq!(posts_on_page(posts.clone(), 2, 0), posts);
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct RenderedPage {
+    pub title: String,
+    pub site: SiteSettings,
+    pub body: Vec<PageToken>,
+    // the same body as a tree, see ast.rs
+    pub document: Document,
+    // the body rendered by render.rs, what templates should embed
+    pub html: String,
+    // only set on paginated index pages
+    pub page: Option<usize>,
+    pub prev_page: Option<usize>,
+    pub next_page: Option<usize>,
+}
+
+impl RenderedPage {
+    fn new(document: Document) -> RenderedPage {
+        let site = config::get_config().site;
+        RenderedPage {
+            title: site.title.clone(),
+            site,
+            html: render::render_document(&document),
+            body: document.to_page_tokens(),
+            document,
+            page: None,
+            prev_page: None,
+            next_page: None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct SearchPage {
+    pub title: String,
+    pub site: SiteSettings,
+    pub query: String,
+    pub results: Vec<SearchResult>,
+}
+
+pub const SEARCH_LIMIT: usize = 20;
+
+fn footer() -> Vec<Block> {
+    return vec![Block::Footer];
+}
+
+// always at least one page, even with no posts
+fn page_count(posts: usize, per_page: usize) -> usize {
+    if per_page == 0 || posts == 0 {
+        return 1;
+    }
+    return (posts + per_page - 1) / per_page;
+}
+
+// pages start at 1, with per_page 0 every post is on it
+fn posts_on_page<T>(posts: Vec<T>, page: usize, per_page: usize) -> Vec<T> {
+    if per_page == 0 {
+        return posts;
+    }
+    return posts.into_iter().skip((page - 1) * per_page).take(per_page).collect();
+}
+
+// how many index pages there are
+pub fn index_page_count() -> usize {
+    let per_page = config::get_config().site.pagination.per_page;
+    return page_count(site_cache::get_all_site_content().len(), per_page);
+}
+
+// pages start at 1, None when asking for one past the end
+pub fn index_page(page: usize) -> Option<RenderedPage> {
+    let page_count = index_page_count();
+    if page == 0 || page > page_count {
+        return None;
+    }
+
+    let mut pages = site_cache::get_all_site_content();
+    pages.sort();
+
+    let pages = posts_on_page(pages, page, config::get_config().site.pagination.per_page);
+
+    let blocks: Vec<Block> = pages.into_iter().map(|p| { p.document.blocks })
+        .intersperse(footer())
+        .flat_map(|x| x)
+        .collect();
+
+    let mut context = RenderedPage::new(Document { blocks });
+    if page_count > 1 {
+        context.page = Some(page);
+        context.prev_page = if page > 1 { Some(page - 1) } else { None };
+        context.next_page = if page < page_count { Some(page + 1) } else { None };
+    }
+
+    Some(context)
+}
+
+// returns the template the post should be rendered with, along with its context
+pub fn post_page(page: SiteContent) -> (String, RenderedPage) {
+    // fall back to the default layout when a post asks for a template that doesn't exist
+    let template = page.template
+        .filter(|t| config::template_exists(t))
+        .unwrap_or(String::from("post"));
+
+    let context = RenderedPage::new(page.document);
+
+    (template, context)
+}
+
+pub fn search_page(query: String) -> SearchPage {
+    let site = config::get_config().site;
+    SearchPage {
+        title: site.title.clone(),
+        site,
+        results: search::search(&query, SEARCH_LIMIT),
+        query,
+    }
+}
@@ -0,0 +1,79 @@
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+use rocket::{Outcome, Request, Response};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest};
+use rocket::response::{self, Responder};
+
+use crate::media::{self, ByteRange};
+
+// like NamedFile, but it answers `Range` requests so video and audio can be seeked through
+// without downloading the whole thing first
+
+// browsers ask for `bytes=0-` and then read as much as they want, an open ended range gets at
+// most this much back and they ask again for the rest
+const MAX_RANGE: u64 = 4 * 1024 * 1024;
+
+// the request's `Range` header, if it had one
+pub struct RangeHeader(pub Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for RangeHeader {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<RangeHeader, ()> {
+        return Outcome::Success(RangeHeader(request.headers().get_one("Range").map(String::from)));
+    }
+}
+
+pub struct RangedFile {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    range: Option<String>,
+}
+
+impl RangedFile {
+    pub fn open(path: PathBuf, range: RangeHeader) -> Option<RangedFile> {
+        let file = File::open(&path).ok()?;
+        let metadata = file.metadata().ok()?;
+        if !metadata.is_file() {
+            return None;
+        }
+        return Some(RangedFile { path, file, len: metadata.len(), range: range.0 });
+    }
+}
+
+impl<'r> Responder<'r> for RangedFile {
+    fn respond_to(self, _request: &Request) -> response::Result<'r> {
+        let RangedFile { path, mut file, len, range } = self;
+
+        let mut response = Response::build();
+        response.raw_header("Content-Type", media::mime_type(&path))
+            .raw_header("Accept-Ranges", "bytes");
+
+        match media::parse_range(range.as_deref(), len) {
+            ByteRange::Whole => {
+                response.sized_body(file);
+            }
+            ByteRange::Partial(start, end) => {
+                let end = end.min(start + MAX_RANGE - 1);
+                let mut body = Vec::with_capacity((end - start + 1) as usize);
+                file.seek(SeekFrom::Start(start))
+                    .and_then(|_| file.by_ref().take(end - start + 1).read_to_end(&mut body))
+                    .map_err(|_| Status::InternalServerError)?;
+
+                response.status(Status::PartialContent)
+                    .raw_header("Content-Range", format!("bytes {}-{}/{}", start, end, len))
+                    .sized_body(Cursor::new(body));
+            }
+            ByteRange::Unsatisfiable => {
+                response.status(Status::RangeNotSatisfiable)
+                    .raw_header("Content-Range", format!("bytes */{}", len));
+            }
+        }
+
+        return response.ok();
+    }
+}
@@ -0,0 +1,456 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::ast::{Align, Block, Document, Footnote, Inline, TableRow};
+use crate::media;
+use crate::tag_handlers;
+use crate::tokens::PageToken;
+
+// turns a post's document into the html for its body. templates embed the result, and the
+// feed and the json api hand out the same thing
+
+#[cfg(test)]
+mod tests {
+    use crate::content_compiler;
+
+    use super::*;
+
+    fn token(token_type: &str, meta: Vec<(&str, &str)>) -> PageToken {
+        PageToken {
+            token_type: token_type.to_string(),
+            meta: meta.into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<HashMap<_, _>>(),
+        }
+    }
+
+    const VOID_ELEMENTS: &[&str] = &["br", "hr", "img", "source"];
+    const BLOCK_ELEMENTS: &[&str] = &["p", "h2", "div", "pre", "hr", "ul", "ol", "figure", "blockquote", "section", "table"];
+
+    // a tiny html parser, just enough to check tags are balanced, properly nested and that
+    // paragraphs only hold inline content
+    fn assert_well_formed(html: &str) {
+        let mut open: Vec<String> = Vec::new();
+        let mut rest = html;
+
+        while let Some(start) = rest.find('<') {
+            let end = rest[start..].find('>').expect("unterminated tag") + start;
+            let tag = &rest[start + 1..end];
+            rest = &rest[end + 1..];
+
+            if let Some(name) = tag.strip_prefix('/') {
+                assert_eq!(open.pop().as_deref(), Some(name), "unbalanced </{}> in {}", name, html);
+                continue;
+            }
+
+            let name = tag.split_whitespace().next().unwrap().trim_end_matches('/');
+            if BLOCK_ELEMENTS.contains(&name) {
+                assert!(!open.iter().any(|t| t == "p"), "<{}> inside a paragraph in {}", name, html);
+            }
+            if !VOID_ELEMENTS.contains(&name) && !tag.ends_with('/') {
+                open.push(name.to_string());
+            }
+        }
+
+        assert!(open.is_empty(), "unclosed {:?} in {}", open, html);
+    }
+
+    #[test]
+    fn broken_token_streams_still_render_well_formed() {
+        let html = render_html(&vec![
+            token("para_end", vec![]),
+            token("para_start", vec![]),
+            token("strong_start", vec![]),
+            token("span", vec![("text", "bold ")]),
+            token("em_start", vec![]),
+            token("span", vec![("text", "both")]),
+            token("strong_end", vec![]),
+            token("span", vec![("text", " italic")]),
+            token("header", vec![("text", "A header in a paragraph")]),
+            token("span", vec![("text", "loose text")]),
+            token("em_end", vec![]),
+        ]);
+
+        assert_well_formed(&html);
+        assert_eq!(html, "<p><strong>bold <em>both</em></strong><em> italic</em></p>\
+            <h2>A header in a paragraph</h2><div class=\"subheading\"></div>\
+            <p>loose text</p>");
+    }
+
+    #[test]
+    fn compiled_posts_render_well_formed() {
+        let contents = include_str!("../content/2020_november/second_post.hmm").to_string();
+        let document = content_compiler::compile_hmm(&contents, &"second_post.hmm".to_string(), &mut Vec::new());
+        let html = render_document(&document);
+
+        assert_well_formed(&html);
+        assert!(html.contains("<p>This is a 2nd blog post test.</p>"));
+    }
+
+    #[test]
+    fn lists_render_nested() {
+        let contents = "- one #[url:x.com|text:x]\n  1. inner\n- two".to_string();
+        let document = content_compiler::compile_hmm(&contents, &"list.hmm".to_string(), &mut Vec::new());
+        let html = render_document(&document);
+
+        assert_well_formed(&html);
+        assert_eq!(html, "<ul><li>one <a href=\"https://x.com\">x</a><ol><li>inner</li></ol></li><li>two</li></ul>");
+    }
+
+    #[test]
+    fn quotes_render_with_attribution() {
+        let contents = "#[quote|cite:Ada|source:example.com/notes]\nOne #[url:x.com|text:x]\n\nTwo\n#[/quote]".to_string();
+        let document = content_compiler::compile_hmm(&contents, &"quote.hmm".to_string(), &mut Vec::new());
+        let html = render_document(&document);
+
+        assert_well_formed(&html);
+        assert_eq!(html, "<figure class=\"quote\"><blockquote cite=\"https://example.com/notes\">\
+            <p>One <a href=\"https://x.com\">x</a></p><p>Two</p></blockquote>\
+            <figcaption>— <cite><a href=\"https://example.com/notes\">Ada</a></cite></figcaption></figure>");
+    }
+
+    #[test]
+    fn footnotes_link_both_ways() {
+        let html = render_html(&vec![
+            token("para_start", vec![]),
+            token("span", vec![("text", "Text")]),
+            token("fn", vec![("fn", "A note"), ("number", "1"), ("anchor", "h-1")]),
+            token("para_end", vec![]),
+            token("footnotes_start", vec![]),
+            token("footnote", vec![("text", "A note"), ("number", "1"), ("anchor", "h-1")]),
+            token("footnotes_end", vec![]),
+        ]);
+
+        assert_well_formed(&html);
+        assert_eq!(html, "<p>Text<sup class=\"footnote-ref\"><a href=\"#fn-h-1\" id=\"fnref-h-1\">1</a></sup></p>\
+            <section class=\"footnotes\"><hr><ol><li id=\"fn-h-1\">A note \
+            <a href=\"#fnref-h-1\" class=\"footnote-backref\" aria-label=\"Back to reference 1\">↩</a></li></ol></section>");
+    }
+
+    #[test]
+    fn tables_render_accessibly() {
+        let contents = "#[table|caption:Times]\n| name | secs |\n| --- | --: |\n| fast | 1 |\n#[/table]".to_string();
+        let document = content_compiler::compile_hmm(&contents, &"table.hmm".to_string(), &mut Vec::new());
+        let html = render_document(&document);
+
+        assert_well_formed(&html);
+        assert_eq!(html, "<table><caption>Times</caption>\
+            <thead><tr><th scope=\"col\">name</th><th scope=\"col\" style=\"text-align: right\">secs</th></tr></thead>\
+            <tbody><tr><td>fast</td><td style=\"text-align: right\">1</td></tr></tbody></table>");
+    }
+
+    #[test]
+    fn video_and_audio_carry_their_types() {
+        let html = render_html(&vec![
+            token("video", vec![("video", "/site-content/h/clip.webm"), ("poster", "/site-content/h/clip.png")]),
+            token("audio", vec![("audio", "/site-content/h/talk.mp3")]),
+        ]);
+        assert_well_formed(&html);
+        assert_eq!(html, "<video controls preload=\"metadata\" poster=\"/site-content/h/clip.png\">\
+            <source src=\"/site-content/h/clip.webm\" type=\"video/webm\"><a href=\"/site-content/h/clip.webm\">Download the video</a></video>\
+            <audio controls preload=\"metadata\"><source src=\"/site-content/h/talk.mp3\" type=\"audio/mpeg\">\
+            <a href=\"/site-content/h/talk.mp3\">Download the audio</a></audio>");
+
+        let html = render_html(&vec![token("video", vec![("video", "clip.mp4"), ("poster", "clip.png"), ("lightbox", "<empty>")])]);
+        assert_eq!(html, "<a href=\"clip.mp4\" class=\"glightbox video\"><img src=\"clip.png\"/></a>");
+    }
+
+    #[test]
+    fn galleries_are_grouped_for_the_lightbox() {
+        let html = render_html(&vec![
+            token("gallery_start", vec![("gallery", "<empty>"), ("name", "h-1")]),
+            token("image", vec![("image", "a.png"), ("alt", "A board"), ("gallery", "h-1")]),
+            token("image", vec![("image", "b.png"), ("alt", "Another"), ("caption", "Move 20")]),
+            token("gallery_end", vec![]),
+        ]);
+        assert_well_formed(&html);
+        assert_eq!(html, "<div class=\"gallery\">\
+            <a href=\"a.png\" class=\"glightbox\" data-gallery=\"h-1\" data-title=\"A board\"><img src=\"a.png\" alt=\"A board\"/></a>\
+            <figure class=\"image\"><a href=\"b.png\" class=\"glightbox\" data-gallery=\"h-1\" data-title=\"Move 20\">\
+            <img src=\"b.png\" alt=\"Another\"/></a><figcaption>Move 20</figcaption></figure>\
+            </div>");
+    }
+
+    #[test]
+    fn captioned_images_are_figures() {
+        let image = token("image", vec![("image", "go.png"), ("alt", "A board"), ("caption", "Move 20"), ("width", "640"), ("height", "480")]);
+        let html = render_html(&vec![token("para_start", vec![]), image.clone(), token("para_end", vec![])]);
+        assert_well_formed(&html);
+        assert_eq!(html, "<figure class=\"image\"><img src=\"go.png\" alt=\"A board\" width=\"640\" height=\"480\"/>\
+            <figcaption>Move 20</figcaption></figure>");
+
+        // in the middle of some text there's nowhere for the caption to go
+        let html = render_html(&vec![token("span", vec![("text", "See ")]), image]);
+        assert_eq!(html, "<p>See <img src=\"go.png\" alt=\"A board\" width=\"640\" height=\"480\"/></p>");
+    }
+
+    #[test]
+    fn text_and_attributes_are_escaped() {
+        let html = render_html(&vec![
+            token("header", vec![("text", "Fish & <Chips>"), ("link", "/post/1?a=\"b\"")]),
+            token("para_start", vec![]),
+            token("span", vec![("text", "1 < 2 ")]),
+            token("url", vec![("href", "https://test.com/?q=a&b"), ("text", "a <link>")]),
+            token("para_end", vec![]),
+        ]);
+
+        assert_eq!(html, "<h2><a href=\"/post/1?a=&quot;b&quot;\">Fish &amp; &lt;Chips&gt;</a></h2>\
+            <div class=\"subheading\"></div>\
+            <p>1 &lt; 2 <a href=\"https://test.com/?q=a&amp;b\">a &lt;link&gt;</a></p>");
+    }
+
+    #[test]
+    fn handlers_render_their_own_tags() {
+        let html = render_html(&vec![token("github_gist", vec![("url", "https://gist.github.com/x.js")])]);
+        assert_eq!(html, "<script src=\"https://gist.github.com/x.js\"></script>");
+
+        // gists are blocks, so one in the middle of some text ends the paragraph
+        let document = content_compiler::compile_hmm(&"Look: #[github_gist|url:x.js] neat".to_string(), &"gist.hmm".to_string(), &mut Vec::new());
+        assert_eq!(render_document(&document), "<p>Look: </p><script src=\"x.js\"></script><p> neat</p>");
+
+        // unknown tokens render to nothing rather than leaking their contents
+        assert_eq!(render_html(&vec![token("title", vec![("title", "Hi")])]), "");
+    }
+}
+
+pub fn escape_html(text: &str) -> String {
+    return text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;");
+}
+
+fn meta<'a>(token: &'a PageToken, key: &str) -> &'a str {
+    return token.meta.get(key).map(|v| v.as_str()).unwrap_or("");
+}
+
+fn render_header(text: &String, date: &Option<String>, link: &Option<String>) -> String {
+    let text = escape_html(text);
+    let heading = match link {
+        Some(link) => format!("<h2><a href=\"{}\">{}</a></h2>", escape_html(link), text),
+        None => format!("<h2>{}</h2>", text),
+    };
+    return format!("{}<div class=\"subheading\">{}</div>", heading, escape_html(date.as_deref().unwrap_or("")));
+}
+
+// the size is what the file says, css scales it and the browser keeps the space for it meanwhile
+fn render_img(src: &String, alt: &Option<String>, width: &Option<u32>, height: &Option<u32>) -> String {
+    let mut attributes = format!(" src=\"{}\"", escape_html(src));
+    if let Some(alt) = alt {
+        attributes.push_str(&format!(" alt=\"{}\"", escape_html(alt)));
+    }
+    if let (Some(width), Some(height)) = (width, height) {
+        attributes.push_str(&format!(" width=\"{}\" height=\"{}\"", width, height));
+    }
+    return format!("<img{}/>", attributes);
+}
+
+// `gallery` is used when the image doesn't say which gallery it's in
+fn render_image(image: &Inline, gallery: Option<&String>) -> String {
+    let (src, alt, caption, lightbox, image_gallery, width, height) = match image {
+        Inline::Image { src, alt, caption, lightbox, gallery, width, height } => (src, alt, caption, *lightbox, gallery, width, height),
+        _ => return String::new(),
+    };
+    let img = render_img(src, alt, width, height);
+    let gallery = image_gallery.as_ref().or(gallery);
+
+    // the lightbox steps through everything with the same data-gallery, and shows data-title
+    // under the image
+    if lightbox || gallery.is_some() {
+        let mut attributes = String::new();
+        if let Some(gallery) = gallery {
+            attributes.push_str(&format!(" data-gallery=\"{}\"", escape_html(gallery)));
+        }
+        if let Some(title) = caption.as_ref().or(alt.as_ref()) {
+            attributes.push_str(&format!(" data-title=\"{}\"", escape_html(title)));
+        }
+        return format!("<a href=\"{}\" class=\"glightbox\"{}>{}</a>", escape_html(src), attributes, img);
+    }
+    return img;
+}
+
+fn render_figure(image: &Inline, gallery: Option<&String>) -> String {
+    return match image {
+        Inline::Image { caption: Some(caption), .. } => format!(
+            "<figure class=\"image\">{}<figcaption>{}</figcaption></figure>",
+            render_image(image, gallery),
+            escape_html(caption),
+        ),
+        _ => render_image(image, gallery),
+    };
+}
+
+fn render_gallery(name: &String, images: &Vec<Inline>) -> String {
+    let images: String = images.iter().map(|image| render_figure(image, Some(name))).collect();
+    return format!("<div class=\"gallery\">{}</div>", images);
+}
+
+// `preload="metadata"` fetches just enough for the length and first frame, the rest comes in as
+// ranges once it's played
+fn render_video(src: &String, poster: &Option<String>, lightbox: bool) -> String {
+    let mime = media::mime_type(Path::new(src));
+    let src = escape_html(src);
+    let poster = poster.as_ref().map(|p| escape_html(p));
+
+    if let (Some(poster), true) = (&poster, lightbox) {
+        return format!("<a href=\"{}\" class=\"glightbox video\"><img src=\"{}\"/></a>", src, poster);
+    }
+    let poster = poster.map(|p| format!(" poster=\"{}\"", p)).unwrap_or_default();
+    return format!(
+        "<video controls preload=\"metadata\"{}><source src=\"{}\" type=\"{}\"><a href=\"{}\">Download the video</a></video>",
+        poster, src, mime, src,
+    );
+}
+
+fn render_audio(src: &String) -> String {
+    let mime = media::mime_type(Path::new(src));
+    let src = escape_html(src);
+    return format!(
+        "<audio controls preload=\"metadata\"><source src=\"{}\" type=\"{}\"><a href=\"{}\">Download the audio</a></audio>",
+        src, mime, src,
+    );
+}
+
+// the attribution line is the cite, linked to the source when there is one. a source on its own
+// is shown as the link itself
+fn render_quote(cite: &Option<String>, source: &Option<String>, blocks: &Vec<Block>) -> String {
+    let href = source.as_ref().map(|s| escape_html(&tag_handlers::link_href(s)));
+    let blockquote = match &href {
+        Some(href) => format!("<blockquote cite=\"{}\">{}</blockquote>", href, render_document_blocks(blocks)),
+        None => format!("<blockquote>{}</blockquote>", render_document_blocks(blocks)),
+    };
+
+    let attribution = match (cite, &href) {
+        (Some(cite), Some(href)) => format!("<a href=\"{}\">{}</a>", href, escape_html(cite)),
+        (Some(cite), None) => escape_html(cite),
+        (None, Some(href)) => format!("<a href=\"{}\">{}</a>", href, escape_html(source.as_ref().unwrap())),
+        (None, None) => return format!("<figure class=\"quote\">{}</figure>", blockquote),
+    };
+    return format!("<figure class=\"quote\">{}<figcaption>— <cite>{}</cite></figcaption></figure>", blockquote, attribution);
+}
+
+fn render_table_row(row: &TableRow, align: &Vec<Align>, header: bool) -> String {
+    let cells: String = row.iter().enumerate().map(|(i, cell)| {
+        let (tag, scope) = if header { ("th", " scope=\"col\"") } else { ("td", "") };
+        let style = match align.get(i).map(|a| a.as_str()).unwrap_or("") {
+            "" => String::new(),
+            align => format!(" style=\"text-align: {}\"", align),
+        };
+        format!("<{}{}{}>{}</{}>", tag, scope, style, render_inlines(cell), tag)
+    }).collect();
+    return format!("<tr>{}</tr>", cells);
+}
+
+// header cells are marked up as column headers so screen readers can announce them with each cell
+fn render_table(caption: &Option<String>, align: &Vec<Align>, head: &Vec<TableRow>, body: &Vec<TableRow>) -> String {
+    let mut html = String::from("<table>");
+    if let Some(caption) = caption {
+        html.push_str(&format!("<caption>{}</caption>", escape_html(caption)));
+    }
+    if !head.is_empty() {
+        let rows: String = head.iter().map(|r| render_table_row(r, align, true)).collect();
+        html.push_str(&format!("<thead>{}</thead>", rows));
+    }
+    let rows: String = body.iter().map(|r| render_table_row(r, align, false)).collect();
+    html.push_str(&format!("<tbody>{}</tbody></table>", rows));
+    return html;
+}
+
+// a formula that couldn't be converted shows its TeX, the compiler has already said what's wrong
+fn render_math(tex: &String, mathml: &Option<String>) -> String {
+    return match mathml {
+        Some(mathml) => mathml.clone(),
+        None => format!("<code class=\"math\">{}</code>", escape_html(tex)),
+    };
+}
+
+// the reference and its note link to each other, so readers can jump down and back again
+fn render_footnote_ref(number: usize, anchor: &String) -> String {
+    let anchor = escape_html(anchor);
+    return format!("<sup class=\"footnote-ref\"><a href=\"#fn-{}\" id=\"fnref-{}\">{}</a></sup>", anchor, anchor, number);
+}
+
+fn render_footnotes(notes: &Vec<Footnote>) -> String {
+    let items: String = notes.iter().map(|note| {
+        let anchor = escape_html(&note.anchor);
+        format!(
+            "<li id=\"fn-{}\">{} <a href=\"#fnref-{}\" class=\"footnote-backref\" aria-label=\"Back to reference {}\">↩</a></li>",
+            anchor, escape_html(&note.text), anchor, note.number,
+        )
+    }).collect();
+    return format!("<section class=\"footnotes\"><hr><ol>{}</ol></section>", items);
+}
+
+// tags the ast doesn't know about render through their handler, or not at all
+fn render_tag(tag_type: &String, meta: &HashMap<String, String>) -> String {
+    let token = PageToken { token_type: tag_type.clone(), meta: meta.clone() };
+    return tag_handlers::get_tag_handler(tag_type)
+        .and_then(|h| h.render(&token))
+        .unwrap_or_default();
+}
+
+fn render_inlines(inlines: &Vec<Inline>) -> String {
+    return inlines.iter().map(render_inline).collect();
+}
+
+pub fn render_inline(inline: &Inline) -> String {
+    return match inline {
+        Inline::Text { text } => escape_html(text),
+        Inline::Emphasis { children } => format!("<em>{}</em>", render_inlines(children)),
+        Inline::Strong { children } => format!("<strong>{}</strong>", render_inlines(children)),
+        Inline::Code { text } => format!("<code>{}</code>", escape_html(text)),
+        Inline::LineBreak => String::from("<br>"),
+        Inline::Link { href, text, .. } => format!("<a href=\"{}\">{}</a>", escape_html(href), escape_html(text)),
+        Inline::Image { .. } => render_image(inline, None),
+        Inline::FootnoteRef { number, anchor, .. } => render_footnote_ref(*number, anchor),
+        Inline::Math { tex, mathml } => render_math(tex, mathml),
+        Inline::Tag { tag_type, meta } => render_tag(tag_type, meta),
+    };
+}
+
+pub fn render_block(block: &Block) -> String {
+    return match block {
+        // the title tag has no visible form, the header is what shows up on the page
+        Block::Title { .. } => String::new(),
+        Block::Header { text, date, link } => render_header(text, date, link),
+        Block::Updated { date, diff } => format!(
+            "<div class=\"subheading\">updated on {} (<a href=\"{}\">diff</a>)</div>",
+            escape_html(date),
+            escape_html(diff),
+        ),
+        Block::Paragraph { children } => format!("<p>{}</p>", render_inlines(children)),
+        Block::List { ordered, items } => {
+            let tag = if *ordered { "ol" } else { "ul" };
+            let items: String = items.iter()
+                .map(|item| format!("<li>{}{}</li>", render_inlines(&item.children), render_document_blocks(&item.sublists)))
+                .collect();
+            format!("<{}>{}</{}>", tag, items, tag)
+        }
+        Block::Quote { cite, source, blocks } => render_quote(cite, source, blocks),
+        Block::CodeBlock { text } => format!("<pre><code>{}</code></pre>", escape_html(text)),
+        Block::Rule | Block::Footer => String::from("<hr>"),
+        Block::Table { caption, align, head, body } => render_table(caption, align, head, body),
+        Block::Math { tex, mathml } => format!("<div class=\"math\">{}</div>", render_math(tex, mathml)),
+        Block::Gallery { name, images } => render_gallery(name, images),
+        Block::Figure { image } => render_figure(image, None),
+        Block::Video { src, poster, lightbox } => render_video(src, poster, *lightbox),
+        Block::Audio { src } => render_audio(src),
+        Block::Footnotes { notes } => render_footnotes(notes),
+        Block::Tag { tag_type, meta } => render_tag(tag_type, meta),
+        Block::Container { tag_type, blocks, .. } => {
+            format!("<div class=\"{}\">{}</div>", escape_html(tag_type), render_document_blocks(blocks))
+        }
+    };
+}
+
+// the tree always nests properly, so the html does too
+pub fn render_document(document: &Document) -> String {
+    return render_document_blocks(&document.blocks);
+}
+
+fn render_document_blocks(blocks: &Vec<Block>) -> String {
+    return blocks.iter().map(render_block).collect();
+}
+
+pub fn render_html(tokens: &[PageToken]) -> String {
+    return render_document(&Document::from_page_tokens(tokens));
+}
@@ -0,0 +1,81 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, TimeZone};
+
+use crate::content_compiler;
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    #[test]
+    fn new_posts_get_a_dated_dir_and_title_tag() {
+        let dir = std::env::temp_dir().join(format!("simple-blog-new-test-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+
+        let now = Utc.timestamp(1605073296, 0);
+        let file = new_post(&dir, "Hello, World!", &vec![String::from("rust"), String::from("thinking")], now).unwrap();
+
+        assert_eq!(file, dir.join("2020_november").join("hello_world.hmm"));
+        let contents = fs::read_to_string(&file).unwrap();
+        assert!(contents.starts_with("#[title:Hello, World!|timestamp:1605073296|tags:rust,thinking|slug:hello-world]"));
+
+        // a second post with the same title would clobber the first
+        assert!(new_post(&dir, "Hello, World!", &vec![], now).is_err());
+        assert!(new_post(&dir, "a | b", &vec![], now).is_err());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
+
+// posts live in a directory per month, e.g. 2020_november
+fn month_dir<Tz: TimeZone>(now: &DateTime<Tz>) -> String where Tz::Offset: std::fmt::Display {
+    return now.format("%Y_%B").to_string().to_lowercase();
+}
+
+fn skeleton(title: &str, timestamp: i64, tags: &Vec<String>, slug: &str) -> String {
+    let mut title_tag = format!("#[title:{}|timestamp:{}", title, timestamp);
+    if !tags.is_empty() {
+        title_tag.push_str(&format!("|tags:{}", tags.join(",")));
+    }
+    title_tag.push_str(&format!("|slug:{}]", slug));
+
+    return format!("{}\n\n#[header|text:{}]\n\n", title_tag, title);
+}
+
+// writes a new post skeleton under content_dir and returns its path, never overwrites anything
+pub fn new_post<Tz: TimeZone>(content_dir: &Path, title: &str, tags: &Vec<String>, now: DateTime<Tz>) -> Result<PathBuf, String>
+    where Tz::Offset: std::fmt::Display {
+    let title = title.trim();
+    // these would end the tag early
+    if title.contains(|c| c == '|' || c == '[' || c == ']') {
+        return Err(format!("titles can't contain '|', '[' or ']': {}", title));
+    }
+    if let Some(tag) = tags.iter().find(|t| t.contains(|c| c == '|' || c == '[' || c == ']' || c == ',')) {
+        return Err(format!("invalid tag '{}'", tag));
+    }
+
+    let slug = content_compiler::slugify(title);
+    if slug.is_empty() {
+        return Err(format!("couldn't make a slug out of '{}'", title));
+    }
+
+    let dir = content_dir.join(month_dir(&now));
+    fs::create_dir_all(&dir).map_err(|e| format!("couldn't create {}: {}", dir.display(), e))?;
+
+    let file = dir.join(format!("{}.hmm", slug.replace('-', "_")));
+    let mut out = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&file)
+        .map_err(|e| format!("couldn't create {}: {}", file.display(), e))?;
+
+    out.write_all(skeleton(title, now.timestamp(), tags, &slug).as_bytes())
+        .map_err(|e| format!("couldn't write {}: {}", file.display(), e))?;
+
+    return Ok(file);
+}
@@ -0,0 +1,295 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+
+use lazy_static::lazy_static;
+use rust_stemmers::{Algorithm, Stemmer};
+use serde::Serialize;
+
+use crate::render::escape_html;
+use crate::site_cache;
+use crate::tokens::SiteContent;
+
+// an in-memory inverted index over the text of every post, rebuilt whenever the site cache is
+// reloaded. terms are lowercased, stemmed and stop words are dropped, so "thinking" finds "thinks"
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::ast::Document;
+    use crate::tokens::PageToken;
+
+    use super::*;
+
+    fn page(hash: &str, title: &str, text: &str, timestamp: u128) -> SiteContent {
+        let token = |token_type: &str, key: &str, value: &str| {
+            let mut meta = HashMap::new();
+            meta.insert(key.to_string(), value.to_string());
+            PageToken { token_type: token_type.to_string(), meta }
+        };
+
+        SiteContent {
+            file_name: format!("{}.hmm", hash),
+            page_hash: hash.to_string(),
+            slug: hash.to_string(),
+            title: format!("{}.hmm", hash),
+            template: None,
+            timestamp,
+            created: None,
+            updated: None,
+            revisions: Vec::new(),
+            document: Document::default(),
+            page_tokens: vec![
+                token("title", "title", title),
+                token("header", "text", title),
+                token("span", "text", text),
+            ],
+        }
+    }
+
+    #[test]
+    fn results_are_stemmed_ranked_and_highlighted() {
+        let index = SearchIndex::build(&vec![
+            page("a", "Gardening", "I was thinking about the tomatoes again.", 1),
+            page("b", "Thinking", "Thoughts on thinking, and then I think some more.", 2),
+            page("c", "Cooking", "Nothing to see here.", 3),
+        ]);
+
+        let results = index.search("thinks", 10);
+        let hashes: Vec<&str> = results.iter().map(|r| r.page_hash.as_str()).collect();
+        assert_eq!(hashes, vec!["b", "a"]);
+        assert!(results[1].snippet.contains("<mark>thinking</mark>"));
+
+        // stop words alone don't match everything
+        assert!(index.search("the and", 10).is_empty());
+    }
+
+    #[test]
+    fn client_index_keeps_unique_words() {
+        let index = client_index(&vec![page("a", "Gardening", "I was thinking about the Tomatoes, tomatoes!", 1)]);
+        assert_eq!(index.posts[0].url, "/post/a");
+        assert_eq!(index.posts[0].words, vec!["gardening", "thinking", "tomatoes"]);
+    }
+}
+
+lazy_static! {
+    static ref SEARCH_INDEX: RwLock<SearchIndex> = RwLock::new(SearchIndex::default());
+}
+
+const STOP_WORDS: &[&str] = &[
+    "a", "about", "after", "all", "also", "am", "an", "and", "any", "are", "as", "at", "be", "because",
+    "been", "but", "by", "can", "could", "did", "do", "does", "for", "from", "had", "has", "have", "he",
+    "her", "his", "how", "i", "if", "in", "into", "is", "it", "its", "just", "me", "my", "no", "not",
+    "of", "on", "or", "our", "out", "she", "so", "than", "that", "the", "their", "them", "then",
+    "there", "these", "they", "this", "to", "too", "up", "us", "was", "we", "were", "what", "when",
+    "which", "who", "will", "with", "would", "you", "your",
+];
+
+// headers count for more than body text
+const HEADER_WEIGHT: u32 = 3;
+const SNIPPET_WORDS: usize = 12;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SearchResult {
+    pub page_hash: String,
+    pub slug: String,
+    pub title: String,
+    pub url: String,
+    pub score: f64,
+    // html, with matching words wrapped in <mark>
+    pub snippet: String,
+}
+
+#[derive(Debug, Clone)]
+struct IndexedPage {
+    slug: String,
+    title: String,
+    timestamp: u128,
+    text: String,
+}
+
+#[derive(Default)]
+pub struct SearchIndex {
+    pages: HashMap<String, IndexedPage>,
+    // term -> page hash -> weighted term frequency
+    postings: HashMap<String, HashMap<String, u32>>,
+}
+
+fn is_word_char(c: char) -> bool {
+    return c.is_alphanumeric() || c == '\'';
+}
+
+fn words(text: &str) -> impl Iterator<Item=&str> {
+    return text.split(|c: char| !is_word_char(c))
+        .map(|w| w.trim_matches('\''))
+        .filter(|w| !w.is_empty());
+}
+
+fn normalize(stemmer: &Stemmer, word: &str) -> Option<String> {
+    let word = word.to_lowercase();
+    if STOP_WORDS.contains(&word.as_str()) {
+        return None;
+    }
+    return Some(stemmer.stem(&word).to_string());
+}
+
+// splits text into the terms that go into the index
+pub fn analyze(text: &str) -> Vec<String> {
+    let stemmer = Stemmer::create(Algorithm::English);
+    return words(text).filter_map(|w| normalize(&stemmer, w)).collect();
+}
+
+// a window of words around the first match, or the start of the post when only the title matched
+fn snippet(text: &str, terms: &HashSet<String>) -> String {
+    let stemmer = Stemmer::create(Algorithm::English);
+    let all_words: Vec<&str> = text.split_whitespace().collect();
+    let matches = |word: &str| {
+        let bare = word.trim_matches(|c: char| !is_word_char(c)).trim_matches('\'');
+        return normalize(&stemmer, bare).map(|t| terms.contains(&t)).unwrap_or(false);
+    };
+
+    let first = all_words.iter().position(|w| matches(w)).unwrap_or(0);
+    let start = first.saturating_sub(SNIPPET_WORDS / 3);
+    let end = (start + SNIPPET_WORDS).min(all_words.len());
+
+    let mut out: Vec<String> = all_words[start..end].iter()
+        .map(|w| if matches(w) { format!("<mark>{}</mark>", escape_html(w)) } else { escape_html(w) })
+        .collect();
+    if start > 0 { out.insert(0, String::from("…")); }
+    if end < all_words.len() { out.push(String::from("…")); }
+    return out.join(" ");
+}
+
+impl SearchIndex {
+    pub fn build(pages: &Vec<SiteContent>) -> SearchIndex {
+        let mut index = SearchIndex::default();
+
+        for page in pages {
+            let mut body: Vec<&str> = Vec::new();
+            let mut weighted_terms: Vec<(String, u32)> = Vec::new();
+
+            for token in page.page_tokens.iter() {
+                let text = match token.meta.get("text") {
+                    Some(text) => text,
+                    None => continue,
+                };
+                let weight = match token.token_type.as_str() {
+                    "header" => HEADER_WEIGHT,
+                    "span" => {
+                        body.push(text);
+                        1
+                    }
+                    _ => continue,
+                };
+                weighted_terms.extend(analyze(text).into_iter().map(|t| (t, weight)));
+            }
+
+            for (term, weight) in weighted_terms {
+                *index.postings.entry(term).or_default().entry(page.page_hash.clone()).or_insert(0) += weight;
+            }
+
+            index.pages.insert(page.page_hash.clone(), IndexedPage {
+                slug: page.slug.clone(),
+                title: page.display_title(),
+                timestamp: page.timestamp,
+                text: body.join(" "),
+            });
+        }
+
+        return index;
+    }
+
+    // tf-idf over the query terms, newest first when scores tie
+    pub fn search(&self, query: &str, limit: usize) -> Vec<SearchResult> {
+        let terms: HashSet<String> = analyze(query).into_iter().collect();
+        let page_count = self.pages.len() as f64;
+
+        let mut scores: HashMap<&String, f64> = HashMap::new();
+        for term in terms.iter() {
+            let postings = match self.postings.get(term) {
+                Some(p) => p,
+                None => continue,
+            };
+            let idf = (1.0 + page_count / postings.len() as f64).ln();
+            for (page_hash, frequency) in postings {
+                *scores.entry(page_hash).or_insert(0.0) += *frequency as f64 * idf;
+            }
+        }
+
+        let mut ranked: Vec<(&String, f64)> = scores.into_iter().collect();
+        ranked.sort_by(|(a_hash, a), (b_hash, b)| {
+            b.partial_cmp(a).unwrap()
+                .then(self.pages[*b_hash].timestamp.cmp(&self.pages[*a_hash].timestamp))
+        });
+
+        return ranked.into_iter().take(limit).map(|(page_hash, score)| {
+            let page = &self.pages[page_hash];
+            SearchResult {
+                page_hash: page_hash.clone(),
+                slug: page.slug.clone(),
+                title: page.title.clone(),
+                url: format!("/post/{}", page_hash),
+                score,
+                snippet: snippet(&page.text, &terms),
+            }
+        }).collect();
+    }
+}
+
+// the static build can't search on the server, so it ships the words of every post for
+// static/search.js to match against. no stemming here, the script matches query words as prefixes
+#[derive(Serialize, Debug)]
+pub struct ClientIndex {
+    pub posts: Vec<ClientIndexEntry>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ClientIndexEntry {
+    pub id: String,
+    pub title: String,
+    pub url: String,
+    pub words: Vec<String>,
+}
+
+pub fn client_index(pages: &Vec<SiteContent>) -> ClientIndex {
+    let mut pages = pages.clone();
+    pages.sort();
+
+    let posts = pages.iter().map(|page| {
+        let mut seen = HashSet::new();
+        let words = page.page_tokens.iter()
+            .filter(|t| t.token_type == "header" || t.token_type == "span")
+            .filter_map(|t| t.meta.get("text"))
+            .flat_map(|text| words(text).map(|w| w.to_lowercase()).collect::<Vec<String>>())
+            .filter(|w| !STOP_WORDS.contains(&w.as_str()))
+            .filter(|w| seen.insert(w.clone()))
+            .collect();
+
+        ClientIndexEntry {
+            id: page.page_hash.clone(),
+            title: page.display_title(),
+            url: format!("/post/{}", page.page_hash),
+            words,
+        }
+    }).collect();
+
+    return ClientIndex { posts };
+}
+
+pub fn client_index_json() -> String {
+    return serde_json::to_string(&client_index(&site_cache::get_all_site_content())).unwrap();
+}
+
+// called whenever the site cache changes
+pub fn rebuild_index() {
+    let index = SearchIndex::build(&site_cache::get_all_site_content());
+    println!("Indexed {} pages, {} terms", index.pages.len(), index.postings.len());
+
+    let mut current = SEARCH_INDEX.write().unwrap();
+    *current = index;
+}
+
+pub fn search(query: &str, limit: usize) -> Vec<SearchResult> {
+    let index = SEARCH_INDEX.read().unwrap();
+    return index.search(query, limit);
+}
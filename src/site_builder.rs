@@ -0,0 +1,78 @@
+use std::fs;
+use std::path::Path;
+
+use rocket::Rocket;
+use rocket_contrib::templates::Template;
+use walkdir::WalkDir;
+
+use crate::config::{self, SiteConfig};
+use crate::content_compiler;
+use crate::feed;
+use crate::git_history;
+use crate::pages;
+use crate::search;
+use crate::site_cache;
+
+fn copy_dir(from: &Path, to: &Path, skip_content: bool) -> Result<(), String> {
+    for entry in WalkDir::new(from).into_iter().filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if !path.is_file() { continue; }
+        if skip_content && content_compiler::is_content_file(path) { continue; }
+
+        let target = to.join(path.strip_prefix(from).unwrap());
+        fs::create_dir_all(target.parent().unwrap()).map_err(|e| e.to_string())?;
+        fs::copy(path, &target).map_err(|e| format!("couldn't copy {}: {}", path.display(), e))?;
+    }
+    return Ok(());
+}
+
+fn write_page(out_dir: &Path, relative: &str, html: Option<String>) -> Result<(), String> {
+    let html = html.ok_or(format!("couldn't render {}", relative))?;
+    let target = out_dir.join(relative);
+    fs::create_dir_all(target.parent().unwrap()).map_err(|e| e.to_string())?;
+    println!("Writing {}", target.display());
+    return fs::write(&target, html).map_err(|e| e.to_string());
+}
+
+// renders every page the server would serve into config.output_dir, along with the static files
+// and each post's assets. expects the site content to already be loaded. of the git history routes
+// only the diffs are written, they're what the "updated" links on posts point at
+pub fn build_site(rocket: &Rocket, config: &SiteConfig) -> Result<(), String> {
+    let out_dir = Path::new(&config.output_dir);
+
+    let mut static_config = config::get_config();
+    static_config.site.static_build = true;
+    config::set_config(static_config);
+
+    write_page(out_dir, "index.html", pages::index_page(1).and_then(|c| Template::show(rocket, "index", c)))?;
+    for page in 1..=pages::index_page_count() {
+        let context = pages::index_page(page);
+        write_page(out_dir, &format!("page/{}/index.html", page), context.and_then(|c| Template::show(rocket, "index", c)))?;
+    }
+
+    if config.site.features.search {
+        write_page(out_dir, "search-index.json", Some(search::client_index_json()))?;
+    }
+
+    if config.site.feed.enabled {
+        write_page(out_dir, "feed.xml", Some(feed::atom_feed(&config.site)))?;
+    }
+
+    for page in site_cache::get_all_site_content() {
+        let page_hash = page.page_hash.clone();
+        for revision in page.revisions.iter() {
+            let diff = git_history::file_diff(Path::new(&page.file_name), &revision.id);
+            write_page(out_dir, &format!("history/{}/{}.diff", page_hash, revision.id), diff)?;
+        }
+
+        let (template, context) = pages::post_page(page);
+        write_page(out_dir, &format!("post/{}/index.html", page_hash), Template::show(rocket, template, context))?;
+
+        if let Some(root) = site_cache::get_page_root(&page_hash) {
+            copy_dir(Path::new(&root), &out_dir.join("site-content").join(&page_hash), true)?;
+        }
+    }
+
+    copy_dir(Path::new(&config.static_dir), &out_dir.join("static"), false)?;
+    return Ok(());
+}
@@ -47,4 +47,14 @@ pub fn get_all_site_content() -> Vec<SiteContent> {
         ret.push(page.clone());
     }
     return ret;
+}
+
+pub fn get_page_by_hash(page_hash: &String) -> Option<SiteContent> {
+    let cache = SITE_CONTENT_CACHE.read().unwrap();
+    for (_, page) in cache.iter() {
+        if page.page_hash == *page_hash {
+            return Some(page.clone());
+        }
+    }
+    return None;
 }
\ No newline at end of file
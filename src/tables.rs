@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::media;
+use crate::tokens::PageToken;
+
+// bits shared by pipe tables, which the compiler builds, and csv tables, which the table tag
+// reads in while resolving files. both end up as the same page tokens:
+// table_start, then row_start (header flag), cell_start, inline tokens, cell_end, row_end..., table_end
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_handles_quotes() {
+        let rows = parse_csv("name,score\r\n\"Smith, J\",\"10 \"\"ish\"\"\"\n\nlast,\n");
+        assert_eq!(rows, vec![
+            vec!["name", "score"],
+            vec!["Smith, J", "10 \"ish\""],
+            vec!["last", ""],
+        ]);
+    }
+
+    #[test]
+    fn alignment_specs() {
+        assert_eq!(separator_alignment(":---"), Some("left"));
+        assert_eq!(separator_alignment(" :-: "), Some("center"));
+        assert_eq!(separator_alignment("--:"), Some("right"));
+        assert_eq!(separator_alignment("---"), Some(""));
+        assert_eq!(separator_alignment("a-b"), None);
+        assert_eq!(parse_align_key("l, right,,c"), vec!["left", "right", "", "center"]);
+    }
+}
+
+// a csv table's file, see media::post_file for where it can be
+pub fn read_csv_file(post_dir: &Path, file: &str) -> Result<String, String> {
+    let path = media::post_file(post_dir, file)?;
+    return fs::read_to_string(&path).map_err(|e| format!("couldn't read '{}': {}", file, e));
+}
+
+// a cell from the `|---|:--:|` row under the header, None if it isn't one
+pub fn separator_alignment(cell: &str) -> Option<&'static str> {
+    let cell = cell.trim();
+    let dashes = cell.trim_start_matches(':').trim_end_matches(':');
+    if dashes.is_empty() || !dashes.chars().all(|c| c == '-') {
+        return None;
+    }
+
+    return match (cell.starts_with(':'), cell.ends_with(':')) {
+        (true, true) => Some("center"),
+        (true, false) => Some("left"),
+        (false, true) => Some("right"),
+        (false, false) => Some(""),
+    };
+}
+
+// `align:l,r,c`, the way csv tables say how their columns line up
+pub fn parse_align_key(value: &str) -> Vec<&'static str> {
+    return value.split(',').map(|a| match a.trim() {
+        "l" | "left" => "left",
+        "c" | "center" => "center",
+        "r" | "right" => "right",
+        _ => "",
+    }).collect();
+}
+
+pub fn is_valid_align_key(value: &str) -> bool {
+    return value.split(',').all(|a| ["", "l", "left", "c", "center", "r", "right"].contains(&a.trim()));
+}
+
+// rfc 4180 near enough: quoted fields can hold commas, newlines and doubled quotes. blank lines
+// are skipped
+pub fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    let mut row: Vec<String> = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if quoted {
+            match ch {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => quoted = false,
+                _ => field.push(ch),
+            }
+            continue;
+        }
+
+        match ch {
+            '"' => quoted = true,
+            ',' => row.push(field.split_off(0)),
+            '\r' => {}
+            '\n' => {
+                row.push(field.split_off(0));
+                if row.len() > 1 || !row[0].is_empty() {
+                    rows.push(row.split_off(0));
+                }
+                row.clear();
+            }
+            _ => field.push(ch),
+        }
+    }
+
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    return rows;
+}
+
+fn marker(token_type: &str, meta: HashMap<String, String>) -> PageToken {
+    return PageToken { token_type: token_type.to_string(), meta };
+}
+
+// `meta` is the table tag's, the first `header_rows` rows go in the table's head
+pub fn table_page_tokens(mut meta: HashMap<String, String>, align: &[&str], header_rows: usize, rows: Vec<Vec<Vec<PageToken>>>) -> Vec<PageToken> {
+    meta.insert("align".to_string(), align.join(","));
+
+    let mut tokens = vec![marker("table_start", meta)];
+    for (i, row) in rows.into_iter().enumerate() {
+        let row_meta = if i < header_rows {
+            vec![("header".to_string(), "<empty>".to_string())].into_iter().collect()
+        } else {
+            HashMap::new()
+        };
+        tokens.push(marker("row_start", row_meta));
+        for cell in row {
+            tokens.push(marker("cell_start", HashMap::new()));
+            tokens.extend(cell);
+            tokens.push(marker("cell_end", HashMap::new()));
+        }
+        tokens.push(marker("row_end", HashMap::new()));
+    }
+    tokens.push(marker("table_end", HashMap::new()));
+
+    return tokens;
+}
@@ -0,0 +1,534 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use lazy_static::lazy_static;
+
+use crate::diagnostics::Severity;
+use crate::math;
+use crate::media;
+use crate::render;
+use crate::tables;
+use crate::tokens::PageToken;
+
+// everything the compiler knows about while it's finishing up a single page
+#[derive(Clone, Copy)]
+pub struct TagContext<'a> {
+    pub root: &'a Path,
+    pub title_hash: &'a String,
+    // the tag is the body of a container, standing on its own rather than sitting in a line of text
+    pub block: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueType {
+    // any non-empty value
+    Text,
+    Integer,
+    // a bare key like `lightbox`, any value is ignored
+    Flag,
+    // a file relative to the post, or a fully qualified uri
+    Path,
+}
+
+#[derive(Debug, Clone)]
+pub struct KeySpec {
+    pub name: &'static str,
+    pub value_type: ValueType,
+    pub required: bool,
+    // a warning when it's missing rather than an error, e.g. an image's alt text
+    pub recommended: bool,
+}
+
+// the keys a tag understands, in the order they're documented
+#[derive(Debug, Clone)]
+pub struct TagSchema {
+    pub keys: Vec<KeySpec>,
+}
+
+impl TagSchema {
+    pub fn new() -> TagSchema {
+        TagSchema { keys: Vec::new() }
+    }
+
+    pub fn required(mut self, name: &'static str, value_type: ValueType) -> TagSchema {
+        self.keys.push(KeySpec { name, value_type, required: true, recommended: false });
+        self
+    }
+
+    pub fn optional(mut self, name: &'static str, value_type: ValueType) -> TagSchema {
+        self.keys.push(KeySpec { name, value_type, required: false, recommended: false });
+        self
+    }
+
+    pub fn recommended(mut self, name: &'static str, value_type: ValueType) -> TagSchema {
+        self.keys.push(KeySpec { name, value_type, required: false, recommended: true });
+        self
+    }
+}
+
+// implement this and register it to teach the compiler a new `#[tag...]` type
+pub trait TagHandler: Send + Sync {
+    // the tag type, i.e. the bit before the first `:` or `|`
+    fn name(&self) -> &'static str;
+
+    // block tags stand on their own and end the current paragraph, inline tags live inside one
+    fn is_block(&self) -> bool { false }
+
+    // containers wrap other blocks, everything up to a matching `#[/name]` goes inside
+    fn is_container(&self) -> bool { false }
+
+    // containers can also be written without a body, e.g. `#[table:data.csv]`
+    fn has_body(&self, _meta: &HashMap<String, String>) -> bool { true }
+
+    // the lexer takes the body as it is, e.g. TeX, instead of looking for tags and lines in it
+    fn raw_body(&self) -> bool { false }
+
+    // the keys this tag understands, None means anything goes
+    fn schema(&self) -> Option<TagSchema> { None }
+
+    // any extra checks the schema can't express
+    fn validate(&self, _meta: &HashMap<String, String>) -> Result<(), String> { Ok(()) }
+
+    // rewrite the token once the page has been compiled, e.g. to resolve paths
+    fn transform(&self, token: PageToken, _ctx: &TagContext) -> PageToken { token }
+
+    // like transform, for tags which turn into several tokens, e.g. a table read from a file
+    fn expand(&self, token: PageToken, ctx: &TagContext) -> Vec<PageToken> {
+        return vec![self.transform(token, ctx)];
+    }
+
+    // custom html for the tag, otherwise render.rs decides
+    fn render(&self, _token: &PageToken) -> Option<String> { None }
+}
+
+lazy_static! {
+    static ref TAG_HANDLERS: RwLock<HashMap<&'static str, Arc<dyn TagHandler>>> = {
+        let mut handlers: HashMap<&'static str, Arc<dyn TagHandler>> = HashMap::new();
+        let built_ins: Vec<Arc<dyn TagHandler>> = vec![
+            Arc::new(TitleTag),
+            Arc::new(HeaderTag),
+            Arc::new(UrlTag),
+            Arc::new(ImageTag),
+            Arc::new(GalleryTag),
+            Arc::new(VideoTag),
+            Arc::new(AudioTag),
+            Arc::new(GithubGistTag),
+            Arc::new(QuoteTag),
+            Arc::new(FootnoteTag),
+            Arc::new(TableTag),
+            Arc::new(MathTag),
+        ];
+        for handler in built_ins {
+            handlers.insert(handler.name(), handler);
+        }
+        RwLock::new(handlers)
+    };
+}
+
+pub fn register_tag_handler(handler: Arc<dyn TagHandler>) {
+    let mut handlers = TAG_HANDLERS.write().unwrap();
+    handlers.insert(handler.name(), handler);
+}
+
+pub fn get_tag_handler(tag_type: &str) -> Option<Arc<dyn TagHandler>> {
+    let handlers = TAG_HANDLERS.read().unwrap();
+    return handlers.get(tag_type).cloned();
+}
+
+// unknown tags are treated as inline, same as they always have been
+pub fn is_block(tag_type: &str) -> bool {
+    return get_tag_handler(tag_type).map(|h| h.is_block()).unwrap_or(false);
+}
+
+pub fn is_container(tag_type: &str) -> bool {
+    return get_tag_handler(tag_type).map(|h| h.is_container()).unwrap_or(false);
+}
+
+// whether this tag starts a container which needs closing
+pub fn opens_container(tag_type: &str, meta: &HashMap<String, String>) -> bool {
+    return get_tag_handler(tag_type).map(|h| h.is_container() && h.has_body(meta)).unwrap_or(false);
+}
+
+pub fn has_raw_body(tag_type: &str, meta: &HashMap<String, String>) -> bool {
+    return opens_container(tag_type, meta) && get_tag_handler(tag_type).map(|h| h.raw_body()).unwrap_or(false);
+}
+
+// the container a `#[/name]` tag closes, if it closes one
+pub fn closed_container(tag_type: &str) -> Option<&str> {
+    return tag_type.strip_prefix('/').filter(|name| is_container(name));
+}
+
+// bare hosts like `test.com` are assumed to be https, anything with a scheme or an absolute path
+// is used as is
+pub fn link_href(url: &String) -> String {
+    if url.contains("://") || url.starts_with('/') || url.starts_with('#') {
+        return url.clone();
+    }
+    return format!("https://{}", url);
+}
+
+fn check_value(value: &String, value_type: ValueType) -> Result<(), String> {
+    let is_empty = value.is_empty() || value == "<empty>";
+    match value_type {
+        ValueType::Flag => Ok(()),
+        ValueType::Text | ValueType::Path if is_empty => Err("expects a value".to_string()),
+        ValueType::Integer if value.parse::<u128>().is_err() => Err(format!("expects a number, got '{}'", value)),
+        _ => Ok(()),
+    }
+}
+
+// checks a parsed tag against its handler's schema
+pub fn check_tag(tag_type: &str, meta: &HashMap<String, String>) -> Vec<(Severity, String)> {
+    let mut problems = Vec::new();
+
+    let handler = match get_tag_handler(tag_type) {
+        Some(h) => h,
+        None => {
+            problems.push((Severity::Warning, format!("unknown tag type '{}'", tag_type)));
+            return problems;
+        }
+    };
+
+    if let Some(schema) = handler.schema() {
+        for spec in schema.keys.iter() {
+            match meta.get(spec.name) {
+                Some(value) => {
+                    if let Err(e) = check_value(value, spec.value_type) {
+                        problems.push((Severity::Error, format!("'{}' key '{}' {}", tag_type, spec.name, e)));
+                    }
+                }
+                None if spec.required => {
+                    problems.push((Severity::Error, format!("'{}' is missing required key '{}'", tag_type, spec.name)));
+                }
+                None if spec.recommended => {
+                    problems.push((Severity::Warning, format!("'{}' is missing recommended key '{}'", tag_type, spec.name)));
+                }
+                None => {}
+            }
+        }
+
+        let mut unknown_keys: Vec<&String> = meta.keys()
+            .filter(|k| !schema.keys.iter().any(|spec| spec.name == k.as_str()))
+            .collect();
+        unknown_keys.sort();
+        for key in unknown_keys {
+            problems.push((Severity::Warning, format!("'{}' has unknown key '{}'", tag_type, key)));
+        }
+    }
+
+    if let Err(e) = handler.validate(meta) {
+        problems.push((Severity::Error, format!("invalid '{}' tag: {}", tag_type, e)));
+    }
+
+    return problems;
+}
+
+struct TitleTag;
+
+impl TagHandler for TitleTag {
+    fn name(&self) -> &'static str { "title" }
+
+    fn is_block(&self) -> bool { true }
+
+    fn schema(&self) -> Option<TagSchema> {
+        Some(TagSchema::new()
+            .required("title", ValueType::Text)
+            .optional("timestamp", ValueType::Integer)
+            .optional("tags", ValueType::Text)
+            .optional("slug", ValueType::Text)
+            .optional("template", ValueType::Text))
+    }
+}
+
+struct HeaderTag;
+
+impl TagHandler for HeaderTag {
+    fn name(&self) -> &'static str { "header" }
+
+    fn is_block(&self) -> bool { true }
+
+    fn schema(&self) -> Option<TagSchema> {
+        Some(TagSchema::new()
+            .required("header", ValueType::Flag)
+            .required("text", ValueType::Text)
+            .optional("date", ValueType::Text))
+    }
+}
+
+struct UrlTag;
+
+impl TagHandler for UrlTag {
+    fn name(&self) -> &'static str { "url" }
+
+    fn schema(&self) -> Option<TagSchema> {
+        Some(TagSchema::new()
+            .required("url", ValueType::Text)
+            .required("text", ValueType::Text))
+    }
+
+    fn transform(&self, token: PageToken, _ctx: &TagContext) -> PageToken {
+        let PageToken { token_type, mut meta } = token;
+        if let Some(url) = meta.get("url") {
+            let href = link_href(url);
+            meta.insert("href".into(), href);
+        }
+        return PageToken { token_type, meta };
+    }
+}
+
+struct ImageTag;
+
+impl TagHandler for ImageTag {
+    fn name(&self) -> &'static str { "image" }
+
+    fn schema(&self) -> Option<TagSchema> {
+        Some(TagSchema::new()
+            .required("image", ValueType::Path)
+            .recommended("alt", ValueType::Text)
+            .optional("caption", ValueType::Text)
+            .optional("lightbox", ValueType::Flag)
+            .optional("gallery", ValueType::Text))
+    }
+
+    fn transform(&self, token: PageToken, ctx: &TagContext) -> PageToken {
+        let PageToken { token_type, mut meta } = token;
+        // with its size on the <img> the page doesn't jump about as images load. only files in the
+        // post's directory are looked at, like a table's csv
+        let size = meta.get("image")
+            .filter(|image| !image.contains("://"))
+            .and_then(|image| media::post_file(ctx.root, image).ok())
+            .and_then(|path| media::read_image_size(&path));
+        if let Some((width, height)) = size {
+            meta.insert("width".into(), width.to_string());
+            meta.insert("height".into(), height.to_string());
+        }
+        resolve_path(&mut meta, "image", ctx);
+        return PageToken { token_type, meta };
+    }
+}
+
+// points a file next to the post at where the site serves it from
+fn resolve_path(meta: &mut HashMap<String, String>, key: &str, ctx: &TagContext) {
+    if let Some(url_path) = meta.get(key) {
+        // leave fully qualified uris alone
+        if !url_path.contains("://") {
+            let site_path = format!("/site-content/{}/{}", ctx.title_hash, url_path);
+            meta.insert(key.into(), site_path);
+        }
+    }
+}
+
+// images between `#[gallery]` and `#[/gallery]` are shown as a grid, and the lightbox steps
+// through them. the compiler names each one, see add_galleries
+struct GalleryTag;
+
+impl TagHandler for GalleryTag {
+    fn name(&self) -> &'static str { "gallery" }
+
+    fn is_block(&self) -> bool { true }
+
+    fn is_container(&self) -> bool { true }
+
+    fn schema(&self) -> Option<TagSchema> {
+        Some(TagSchema::new()
+            .required("gallery", ValueType::Flag))
+    }
+}
+
+// `#[video:clip.mp4|poster:clip.png]`, the poster is shown until it's played. with `lightbox` the
+// poster opens the clip in the lightbox instead, which starts it playing
+struct VideoTag;
+
+impl TagHandler for VideoTag {
+    fn name(&self) -> &'static str { "video" }
+
+    fn is_block(&self) -> bool { true }
+
+    fn schema(&self) -> Option<TagSchema> {
+        Some(TagSchema::new()
+            .required("video", ValueType::Path)
+            .optional("poster", ValueType::Path)
+            .optional("lightbox", ValueType::Flag))
+    }
+
+    fn validate(&self, meta: &HashMap<String, String>) -> Result<(), String> {
+        if meta.contains_key("lightbox") && !meta.contains_key("poster") {
+            return Err("'lightbox' needs a 'poster' to show in the page".to_string());
+        }
+        return Ok(());
+    }
+
+    fn transform(&self, token: PageToken, ctx: &TagContext) -> PageToken {
+        let PageToken { token_type, mut meta } = token;
+        resolve_path(&mut meta, "video", ctx);
+        resolve_path(&mut meta, "poster", ctx);
+        return PageToken { token_type, meta };
+    }
+}
+
+struct AudioTag;
+
+impl TagHandler for AudioTag {
+    fn name(&self) -> &'static str { "audio" }
+
+    fn is_block(&self) -> bool { true }
+
+    fn schema(&self) -> Option<TagSchema> {
+        Some(TagSchema::new()
+            .required("audio", ValueType::Path))
+    }
+
+    fn transform(&self, token: PageToken, ctx: &TagContext) -> PageToken {
+        let PageToken { token_type, mut meta } = token;
+        resolve_path(&mut meta, "audio", ctx);
+        return PageToken { token_type, meta };
+    }
+}
+
+struct GithubGistTag;
+
+impl TagHandler for GithubGistTag {
+    fn name(&self) -> &'static str { "github_gist" }
+
+    // the embed writes a whole block of its own into the page, a paragraph can't hold it
+    fn is_block(&self) -> bool { true }
+
+    fn schema(&self) -> Option<TagSchema> {
+        Some(TagSchema::new()
+            .required("github_gist", ValueType::Flag)
+            .required("url", ValueType::Text))
+    }
+
+    fn render(&self, token: &PageToken) -> Option<String> {
+        let url = token.meta.get("url")?;
+        return Some(format!("<script src=\"{}\"></script>", render::escape_html(url)));
+    }
+}
+
+// numbered and collected at the end of the post by the compiler, see add_footnotes
+struct FootnoteTag;
+
+impl TagHandler for FootnoteTag {
+    fn name(&self) -> &'static str { "fn" }
+
+    fn schema(&self) -> Option<TagSchema> {
+        Some(TagSchema::new()
+            .required("fn", ValueType::Text))
+    }
+}
+
+struct QuoteTag;
+
+impl TagHandler for QuoteTag {
+    fn name(&self) -> &'static str { "quote" }
+
+    fn is_block(&self) -> bool { true }
+
+    fn is_container(&self) -> bool { true }
+
+    fn schema(&self) -> Option<TagSchema> {
+        Some(TagSchema::new()
+            .required("quote", ValueType::Flag)
+            .optional("cite", ValueType::Text)
+            .optional("source", ValueType::Text))
+    }
+}
+
+// `#[table]` with pipe rows up to `#[/table]`, which the compiler turns into table tokens, or
+// `#[table:data.csv]` with the rows read from a file next to the post
+struct TableTag;
+
+impl TagHandler for TableTag {
+    fn name(&self) -> &'static str { "table" }
+
+    fn is_block(&self) -> bool { true }
+
+    fn is_container(&self) -> bool { true }
+
+    fn has_body(&self, meta: &HashMap<String, String>) -> bool {
+        return meta.get("table").map(|v| v == "<empty>").unwrap_or(true);
+    }
+
+    fn schema(&self) -> Option<TagSchema> {
+        Some(TagSchema::new()
+            .required("table", ValueType::Flag)
+            .optional("caption", ValueType::Text)
+            .optional("align", ValueType::Text))
+    }
+
+    fn validate(&self, meta: &HashMap<String, String>) -> Result<(), String> {
+        match meta.get("align") {
+            Some(align) if !tables::is_valid_align_key(align) => Err(format!("'{}' isn't a list of l, c or r", align)),
+            _ => Ok(()),
+        }
+    }
+
+    fn expand(&self, token: PageToken, ctx: &TagContext) -> Vec<PageToken> {
+        let file = match token.meta.get("table") {
+            Some(file) if file != "<empty>" => file,
+            _ => return vec![token],
+        };
+
+        // the compiler reports why at the tag, the table is just left empty
+        let contents = match tables::read_csv_file(ctx.root, file) {
+            Ok(contents) => contents,
+            Err(_) => return vec![token],
+        };
+
+        // the first row of a csv file is always its header
+        let rows = tables::parse_csv(&contents).into_iter().map(|row| {
+            row.into_iter().map(|cell| {
+                let span = PageToken {
+                    token_type: "span".to_string(),
+                    meta: vec![("text".to_string(), cell)].into_iter().collect(),
+                };
+                vec![span]
+            }).collect()
+        }).collect();
+        let align = token.meta.get("align").map(|a| tables::parse_align_key(a)).unwrap_or_default();
+
+        let mut meta = token.meta;
+        meta.remove("table");
+        return tables::table_page_tokens(meta, &align, 1, rows);
+    }
+}
+
+// `#[math:x^2]` inline, or a display formula between `#[math]` and `#[/math]`. the TeX is turned
+// into MathML here, problems with it are reported with their position by the compiler
+struct MathTag;
+
+impl TagHandler for MathTag {
+    fn name(&self) -> &'static str { "math" }
+
+    fn is_container(&self) -> bool { true }
+
+    fn has_body(&self, meta: &HashMap<String, String>) -> bool {
+        return meta.get("math").map(|v| v == "<empty>").unwrap_or(true);
+    }
+
+    fn raw_body(&self) -> bool { true }
+
+    fn schema(&self) -> Option<TagSchema> {
+        Some(TagSchema::new()
+            .required("math", ValueType::Flag))
+    }
+
+    fn validate(&self, meta: &HashMap<String, String>) -> Result<(), String> {
+        match ["display", "mathml"].iter().find(|key| meta.contains_key(**key)) {
+            Some(key) => Err(format!("'{}' is worked out by the compiler and can't be set", key)),
+            None => Ok(()),
+        }
+    }
+
+    // whatever the post wrote for `mathml` and `display` is dropped, the mathml is raw html
+    fn transform(&self, token: PageToken, ctx: &TagContext) -> PageToken {
+        let PageToken { token_type, mut meta } = token;
+        meta.remove("mathml");
+        meta.remove("display");
+        if let Some(Ok(mathml)) = meta.get("math").map(|tex| math::tex_to_mathml(tex, ctx.block)) {
+            meta.insert("mathml".into(), mathml);
+        }
+        return PageToken { token_type, meta };
+    }
+}
@@ -2,12 +2,43 @@ use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 
-#[derive(Debug, Clone)]
+use crate::ast::Document;
+use crate::git_history::Revision;
+
+#[derive(Serialize, Debug, Clone)]
 pub struct SiteContent {
     pub file_name: String,
+    pub page_hash: String,
+    pub slug: String,
     pub title: String,
+    pub template: Option<String>,
     pub timestamp: u128,
+    pub created: Option<u128>,
+    pub updated: Option<u128>,
+    pub revisions: Vec<Revision>,
+    // the legacy flat form of `document`
     pub page_tokens: Vec<PageToken>,
+    pub document: Document,
+}
+
+impl SiteContent {
+    // `title` is really the file name, the title tag has what readers should see
+    pub fn display_title(&self) -> String {
+        return self.page_tokens.iter()
+            .find(|t| t.token_type == "title")
+            .and_then(|t| t.meta.get("title"))
+            .map(|t| t.trim().to_string())
+            .unwrap_or(self.slug.clone());
+    }
+
+    // from the title tag's comma separated `tags:` key
+    pub fn tags(&self) -> Vec<String> {
+        return self.page_tokens.iter()
+            .find(|t| t.token_type == "title")
+            .and_then(|t| t.meta.get("tags"))
+            .map(|tags| tags.split(',').map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect())
+            .unwrap_or_default();
+    }
 }
 
 impl PartialEq for SiteContent {
@@ -35,12 +66,23 @@ pub enum Token {
     Tag(Box<str>, String),
     Span(String),
     Newline,
-    StartParagraph,
-    EndParagraph,
+    // the inline tokens between two blocks
+    Paragraph(Vec<Token>),
+    // a `- ` or `1. ` line, whether it's ordered and how far it's indented
+    ListItem(bool, usize),
+    // ordered or not, and each item's inline tokens followed by any lists nested in it
+    List(bool, Vec<Vec<Token>>),
+    // a container tag's type and contents, and the blocks between it and its `#[/type]`
+    Container(Box<str>, String, Vec<Token>),
+    // a table tag's contents, each column's alignment, how many rows are header rows, and the rows
+    // with each cell's inline tokens
+    Table(String, Vec<String>, usize, Vec<Vec<Vec<Token>>>),
+    // the body of a container like `#[math]`, exactly as it was written
+    Raw(String),
     EOF,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct PageToken {
     pub token_type: String,
     pub meta: HashMap<String, String>,
@@ -0,0 +1,69 @@
+// searches search-index.json in the browser so the box also works on a static build. every word
+// typed has to be the start of some word in the post, posts with the most matching words win
+(function () {
+    const box = document.querySelector('#search-box');
+    const results = document.querySelector('#search-results');
+    let index = null;
+
+    function loadIndex() {
+        if (index !== null) {
+            return Promise.resolve(index);
+        }
+        return fetch('/search-index.json')
+            .then(function (response) { return response.json(); })
+            .then(function (json) { index = json.posts; return index; });
+    }
+
+    function score(post, terms) {
+        let total = 0;
+        for (const term of terms) {
+            const hits = post.words.filter(function (w) { return w.startsWith(term); }).length;
+            if (hits === 0) {
+                return 0;
+            }
+            total += hits;
+        }
+        return total;
+    }
+
+    function show(posts) {
+        results.innerHTML = '';
+        for (const post of posts) {
+            const item = document.createElement('li');
+            const link = document.createElement('a');
+            link.href = post.url;
+            link.textContent = post.title;
+            item.appendChild(link);
+            results.appendChild(item);
+        }
+    }
+
+    // enter goes to the top result. without one it falls back to the server's search page, which
+    // a static build doesn't have
+    box.form.addEventListener('submit', function (e) {
+        const first = results.querySelector('a');
+        if (first !== null || box.form.hasAttribute('data-static')) {
+            e.preventDefault();
+        }
+        if (first !== null) {
+            window.location.href = first.href;
+        }
+    });
+
+    box.addEventListener('input', function () {
+        const terms = box.value.toLowerCase().split(/[^\p{L}\p{N}']+/u).filter(function (t) { return t.length > 0; });
+        if (terms.length === 0) {
+            show([]);
+            return;
+        }
+        loadIndex().then(function (posts) {
+            const ranked = posts
+                .map(function (post) { return { post: post, score: score(post, terms) }; })
+                .filter(function (r) { return r.score > 0; })
+                .sort(function (a, b) { return b.score - a.score; })
+                .slice(0, 10)
+                .map(function (r) { return r.post; });
+            show(ranked);
+        });
+    });
+})();